@@ -0,0 +1,20 @@
+set -e
+
+# Compares the generic automaton against the bitboards on a generated layout,
+# by default 4096x4096, or on the layout file given as the third argument.
+# Both take minutes at that size, so only a few runs.
+SIZE=${1:-4096}
+RUNS=${2:-3}
+
+LAYOUT=$(mktemp)
+trap 'rm -f "$LAYOUT"' EXIT
+
+if [ -n "$3" ]; then
+    cp "$3" "$LAYOUT"
+else
+    ./target/release/2020_11 --generate $SIZE > "$LAYOUT"
+fi
+
+hyperfine --runs $RUNS -N -u second --style basic \
+    "./target/release/2020_11 --input '$LAYOUT'" \
+    "./target/release/2020_11 --input '$LAYOUT' --bitboard"
@@ -1,5 +1,6 @@
 use ahash::{AHashMap, AHashSet};
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use std::fmt::Write;
 use std::fs;
 
 const MY_BAG: &str = "shiny gold";
@@ -8,6 +9,24 @@ const MY_BAG: &str = "shiny gold";
 struct Cli {
     #[clap(short, long)]
     input: String,
+
+    /// Print the bag containment graph instead of the puzzle answers
+    #[clap(long, value_enum)]
+    export: Option<ExportFormat>,
+
+    /// Only export bags reachable from (contained, at any depth, in) this bag
+    #[clap(long, requires = "export")]
+    from: Option<String>,
+
+    /// Only export bags that lead to (eventually contain) this bag
+    #[clap(long, requires = "export")]
+    to: Option<String>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    Dot,
+    Mermaid,
 }
 
 fn parse_bag_contents(inp: &str) -> Vec<(usize, &str)> {
@@ -55,6 +74,14 @@ fn calculate_p1(data: &[(&str, Vec<(usize, &str)>)]) -> usize {
 }
 
 fn calculate_p2(data: &[(&str, Vec<(usize, &str)>)]) -> usize {
+    *contained_counts(data)
+        .get(MY_BAG)
+        .expect("p2 calculation failed")
+}
+
+/// Total number of bags contained (at any depth) in each bag. Bags which are
+/// part of a containment cycle have no finite count and are left out.
+fn contained_counts<'a>(data: &[(&'a str, Vec<(usize, &'a str)>)]) -> AHashMap<&'a str, usize> {
     let mut map: AHashMap<&str, usize> = AHashMap::with_capacity(data.len());
 
     let mut any_changed = true;
@@ -62,26 +89,139 @@ fn calculate_p2(data: &[(&str, Vec<(usize, &str)>)]) -> usize {
         any_changed = false;
         for (bag, subbags) in data {
             if !map.contains_key(bag) && subbags.iter().all(|x| map.contains_key(x.1)) {
-                let mut c = 1;
-                for (n, bag_type) in subbags.iter() {
-                    c += n * map.get(bag_type).unwrap();
-                }
+                let c = subbags
+                    .iter()
+                    .map(|(n, bag_type)| n * (map[bag_type] + 1))
+                    .sum();
                 map.insert(bag, c);
                 any_changed = true;
             }
         }
-        if let Some(result) = map.get(MY_BAG) {
-            return result - 1;
+    }
+    map
+}
+
+fn reachable<'a>(edges: &AHashMap<&'a str, Vec<&'a str>>, start: &'a str) -> AHashSet<&'a str> {
+    let mut seen: AHashSet<&str> = AHashSet::default();
+    let mut queue = vec![start];
+    while let Some(bag) = queue.pop() {
+        if seen.insert(bag) {
+            queue.extend(edges.get(bag).into_iter().flatten());
+        }
+    }
+    seen
+}
+
+/// Bags (in input order) and edges which survive the optional `from`/`to` restriction.
+#[allow(clippy::type_complexity)]
+fn export_subgraph<'a>(
+    data: &[(&'a str, Vec<(usize, &'a str)>)],
+    from: Option<&'a str>,
+    to: Option<&'a str>,
+) -> Result<(Vec<&'a str>, Vec<(&'a str, usize, &'a str)>), String> {
+    let mut nodes: Vec<&str> = vec![];
+    let mut known: AHashSet<&str> = AHashSet::default();
+    let mut contents: AHashMap<&str, Vec<&str>> = AHashMap::default();
+    let mut containers: AHashMap<&str, Vec<&str>> = AHashMap::default();
+
+    for (bag, subbags) in data {
+        for &bag in std::iter::once(bag).chain(subbags.iter().map(|(_, b)| b)) {
+            if known.insert(bag) {
+                nodes.push(bag);
+            }
+        }
+        for &(_, subbag) in subbags {
+            contents.entry(bag).or_default().push(subbag);
+            containers.entry(subbag).or_default().push(bag);
         }
     }
 
-    panic!("p2 calculation failed");
+    if let Some(bag) = [from, to]
+        .into_iter()
+        .flatten()
+        .find(|bag| !known.contains(bag))
+    {
+        return Err(format!("unknown bag: {}", bag));
+    }
+
+    let from_set = from.map(|bag| reachable(&contents, bag));
+    let to_set = to.map(|bag| reachable(&containers, bag));
+    let keep = |bag: &str| {
+        [&from_set, &to_set]
+            .into_iter()
+            .flatten()
+            .all(|s| s.contains(bag))
+    };
+
+    let edges = data
+        .iter()
+        .flat_map(|(bag, subbags)| subbags.iter().map(move |&(n, subbag)| (*bag, n, subbag)))
+        .filter(|&(bag, _, subbag)| keep(bag) && keep(subbag))
+        .collect();
+    nodes.retain(|&bag| keep(bag));
+
+    Ok((nodes, edges))
+}
+
+fn node_label(bag: &str, counts: &AHashMap<&str, usize>) -> String {
+    match counts.get(bag) {
+        Some(c) => format!("{} ({})", bag, c),
+        None => format!("{} (cycle)", bag),
+    }
+}
+
+fn export(
+    data: &[(&str, Vec<(usize, &str)>)],
+    format: ExportFormat,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Result<String, String> {
+    let counts = contained_counts(data);
+    let (nodes, edges) = export_subgraph(data, from, to)?;
+    let mut out = String::new();
+
+    match format {
+        ExportFormat::Dot => {
+            writeln!(out, "digraph bags {{").unwrap();
+            for bag in &nodes {
+                writeln!(
+                    out,
+                    "    \"{}\" [label=\"{}\"];",
+                    bag,
+                    node_label(bag, &counts)
+                )
+                .unwrap();
+            }
+            for (bag, n, subbag) in &edges {
+                writeln!(out, "    \"{}\" -> \"{}\" [label=\"{}\"];", bag, subbag, n).unwrap();
+            }
+            writeln!(out, "}}").unwrap();
+        }
+        ExportFormat::Mermaid => {
+            let ids: AHashMap<&str, usize> =
+                nodes.iter().enumerate().map(|(i, &b)| (b, i)).collect();
+            writeln!(out, "graph LR").unwrap();
+            for bag in &nodes {
+                writeln!(out, "    n{}[\"{}\"]", ids[bag], node_label(bag, &counts)).unwrap();
+            }
+            for (bag, n, subbag) in &edges {
+                writeln!(out, "    n{} -->|{}| n{}", ids[bag], n, ids[subbag]).unwrap();
+            }
+        }
+    }
+    Ok(out)
 }
 
 fn main() {
     let args = Cli::parse();
     let raw_inp = fs::read_to_string(args.input).expect("can't open input file");
     let data = parse(&raw_inp);
+    if let Some(format) = args.export {
+        let out = export(&data, format, args.from.as_deref(), args.to.as_deref())
+            .unwrap_or_else(|e| panic!("{}", e));
+        print!("{}", out);
+        return;
+    }
     let p1 = calculate_p1(&data);
     let p2 = calculate_p2(&data);
     println!("{}\n{}", p1, p2);
@@ -126,6 +266,59 @@ mod tests {
         assert_eq!(calculate_p2(&parse(&P2_TEST_DATA)), 126);
     }
 
+    #[test]
+    fn test_contained_counts() {
+        let data = parse(P2_TEST_DATA);
+        let counts = contained_counts(&data);
+        assert_eq!(counts["shiny gold"], 126);
+        assert_eq!(counts["dark blue"], 2);
+        assert_eq!(counts["dark violet"], 0);
+    }
+
+    #[test]
+    fn test_export_dot_from() {
+        let data = parse(P1_TEST_DATA);
+        assert_eq!(
+            export(&data, ExportFormat::Dot, Some("dark olive"), None).unwrap(),
+            "digraph bags {
+    \"faded blue\" [label=\"faded blue (0)\"];
+    \"dark olive\" [label=\"dark olive (7)\"];
+    \"dotted black\" [label=\"dotted black (0)\"];
+    \"dark olive\" -> \"faded blue\" [label=\"3\"];
+    \"dark olive\" -> \"dotted black\" [label=\"4\"];
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_export_mermaid_to() {
+        let data = parse(P1_TEST_DATA);
+        assert_eq!(
+            export(&data, ExportFormat::Mermaid, None, Some("bright white")).unwrap(),
+            "graph LR
+    n0[\"light red (186)\"]
+    n1[\"bright white (33)\"]
+    n2[\"dark orange (406)\"]
+    n0 -->|1| n1
+    n2 -->|3| n1
+"
+        );
+    }
+
+    #[test]
+    fn test_export_unknown_bag() {
+        let data = parse(P1_TEST_DATA);
+        assert_eq!(
+            export(&data, ExportFormat::Dot, Some("plaid purple"), None),
+            Err("unknown bag: plaid purple".to_string())
+        );
+        assert_eq!(
+            export(&data, ExportFormat::Mermaid, None, Some("neon green")),
+            Err("unknown bag: neon green".to_string())
+        );
+    }
+
     #[test]
     fn test_p1_real() {
         assert_eq!(calculate_p1(&parse(&REAL_DATA)), 229);
@@ -1,86 +1,615 @@
-use ahash::AHashSet;
+use ahash::AHashMap;
 use clap::Parser;
+use std::fmt;
 use std::fs;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
 
 #[derive(Parser)]
 struct Cli {
     #[clap(short, long)]
     input: String,
+
+    /// Print every executed instruction until the program halts or loops
+    #[clap(long)]
+    trace: bool,
+
+    /// Start an interactive debugger on stdin
+    #[clap(long, conflicts_with = "trace")]
+    debug: bool,
+
+    /// Read the input as assembly source, with labels and comments
+    #[clap(long)]
+    assemble: bool,
+
+    /// Allow the extended opcodes in assembly source
+    #[clap(long, requires = "assemble")]
+    extended: bool,
+
+    /// Print the program in the puzzle format instead of running it
+    #[clap(long, conflicts_with_all = ["trace", "debug"])]
+    disassemble: bool,
+}
+
+/// An opcode outside of the core acc/jmp/nop set. `exec` updates the
+/// accumulator and returns the offset to move the pc by.
+#[derive(Debug)]
+struct Opcode {
+    name: &'static str,
+    exec: fn(&mut isize, isize) -> isize,
+}
+
+impl PartialEq for Opcode {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for Opcode {}
+
+const EXTENDED_OPCODES: &[Opcode] = &[
+    Opcode {
+        name: "mul",
+        exec: |acc, n| {
+            *acc *= n;
+            1
+        },
+    },
+    Opcode {
+        name: "jnz",
+        exec: |acc, n| if *acc != 0 { n } else { 1 },
+    },
+];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Instruction {
+    Acc(isize),
+    Jmp(isize),
+    Nop(isize),
+    Ext(&'static Opcode, isize),
+}
+
+impl Instruction {
+    /// The jmp/nop swap used to repair corrupted boot code.
+    fn flipped(self) -> Option<Instruction> {
+        match self {
+            Instruction::Jmp(n) => Some(Instruction::Nop(n)),
+            Instruction::Nop(n) => Some(Instruction::Jmp(n)),
+            Instruction::Acc(_) | Instruction::Ext(..) => None,
+        }
+    }
+
+    fn from_parts(inst: &str, n: isize, extensions: &'static [Opcode]) -> Result<Self, String> {
+        match inst {
+            "acc" => Ok(Instruction::Acc(n)),
+            "jmp" => Ok(Instruction::Jmp(n)),
+            "nop" => Ok(Instruction::Nop(n)),
+            _ => extensions
+                .iter()
+                .find(|op| op.name == inst)
+                .map(|op| Instruction::Ext(op, n))
+                .ok_or_else(|| format!("unknown instruction '{}'", inst)),
+        }
+    }
 }
 
-fn parse(raw_inp: &str) -> Vec<(&str, isize)> {
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::Acc(n) => write!(f, "acc {:+}", n),
+            Instruction::Jmp(n) => write!(f, "jmp {:+}", n),
+            Instruction::Nop(n) => write!(f, "nop {:+}", n),
+            Instruction::Ext(op, n) => write!(f, "{} {:+}", op.name, n),
+        }
+    }
+}
+
+impl FromStr for Instruction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (inst, n) = s.trim().split_once(' ').ok_or("missing argument")?;
+        let n: isize = n.parse().or(Err("can't parse argument"))?;
+        Instruction::from_parts(inst, n, &[])
+    }
+}
+
+fn parse(raw_inp: &str) -> Vec<Instruction> {
     raw_inp
         .trim()
-        .split('\n')
-        .map(|x| {
-            let (inst, n) = x.split_once(' ').expect("parse fail");
-            let ni: isize = n.parse().expect("parse as int failed");
-            (inst, ni)
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| {
+            line.parse()
+                .unwrap_or_else(|e| panic!("parse failed on line {}: {}", idx + 1, e))
+        })
+        .collect()
+}
+
+fn is_label(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Assembles source which may contain comments (starting with `#` or `;`)
+/// and labels (`name:`, alone or before an instruction). An argument naming a
+/// label is replaced by the relative offset to it.
+fn assemble(src: &str, extensions: &'static [Opcode]) -> Result<Vec<Instruction>, String> {
+    let mut labels: AHashMap<&str, usize> = AHashMap::default();
+    let mut lines: Vec<(usize, &str)> = vec![];
+
+    for (idx, line) in src.lines().enumerate() {
+        let mut line = line.split(['#', ';']).next().unwrap_or_default().trim();
+
+        if let Some((label, rest)) = line.split_once(':') {
+            let label = label.trim();
+            if !is_label(label) {
+                return Err(format!("line {}: invalid label '{}'", idx + 1, label));
+            }
+            if labels.insert(label, lines.len()).is_some() {
+                return Err(format!("line {}: duplicate label '{}'", idx + 1, label));
+            }
+            line = rest.trim();
+        }
+
+        if !line.is_empty() {
+            lines.push((idx + 1, line));
+        }
+    }
+
+    lines
+        .iter()
+        .enumerate()
+        .map(|(pc, &(line_no, line))| {
+            let (inst, arg) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| format!("line {}: missing argument", line_no))?;
+            let arg = arg.trim();
+
+            let n = match labels.get(arg) {
+                Some(&target) => target as isize - pc as isize,
+                None if is_label(arg) => {
+                    return Err(format!("line {}: unknown label '{}'", line_no, arg))
+                }
+                None => arg
+                    .parse()
+                    .map_err(|_| format!("line {}: can't parse argument '{}'", line_no, arg))?,
+            };
+            Instruction::from_parts(inst, n, extensions)
+                .map_err(|e| format!("line {}: {}", line_no, e))
         })
         .collect()
 }
 
-fn simulate(data: &[(&str, isize)], swap: Option<usize>) -> (isize, bool) {
-    let mut acc: isize = 0;
-    let mut offset: usize = 0;
+fn disassemble(program: &[Instruction]) -> String {
+    program.iter().map(|inst| format!("{}\n", inst)).collect()
+}
 
-    let mut hs: AHashSet<usize> = AHashSet::default();
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Halt {
+    /// The program counter is exactly one past the last instruction.
+    Finished,
+    /// The next instruction has already been executed once.
+    Loop,
+    StepLimit,
+    /// A jump left the program somewhere other than just past the end.
+    OutOfBounds,
+    Breakpoint(Breakpoint),
+}
 
-    while !hs.contains(&offset) {
-        hs.insert(offset);
+/// Checked after each executed instruction.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Breakpoint {
+    /// The pc lands on this instruction.
+    Pc(usize),
+    /// The accumulator changes to this value.
+    Acc(isize),
+}
+
+impl Breakpoint {
+    fn hit(&self, machine: &Machine, prev_acc: isize) -> bool {
+        match *self {
+            Breakpoint::Pc(pc) => machine.pc == pc,
+            Breakpoint::Acc(acc) => machine.acc == acc && prev_acc != acc,
+        }
+    }
+}
 
-        let inst = data[offset].0;
-        let n = data[offset].1;
+#[derive(Debug, Copy, Clone, Default)]
+struct HaltConditions<'a> {
+    detect_loops: bool,
+    /// Total number of steps since the last reset.
+    step_limit: Option<usize>,
+    breakpoints: &'a [Breakpoint],
+}
+
+#[derive(Debug, Clone)]
+struct Machine {
+    program: Vec<Instruction>,
+    pc: usize,
+    acc: isize,
+    steps: usize,
+    visited: Vec<bool>,
+}
+
+impl Machine {
+    fn new(program: Vec<Instruction>) -> Self {
+        let visited = vec![false; program.len()];
+        Machine {
+            program,
+            pc: 0,
+            acc: 0,
+            steps: 0,
+            visited,
+        }
+    }
+
+    /// Clears registers and loop-detection state. Patches are kept.
+    fn reset(&mut self) {
+        self.pc = 0;
+        self.acc = 0;
+        self.steps = 0;
+        self.visited.fill(false);
+    }
+
+    /// Replaces the instruction at `pc`, returning the previous one.
+    fn patch(&mut self, pc: usize, inst: Instruction) -> Instruction {
+        std::mem::replace(&mut self.program[pc], inst)
+    }
 
-        let do_swap = swap.map_or(false, |s| offset == s);
-        let (was_jmp, was_nop) = (inst == "jmp", inst == "nop");
-        let (is_jmp, is_nop) = if do_swap {
-            (was_nop, was_jmp)
-        } else {
-            (was_jmp, was_nop)
+    fn step(&mut self) -> Result<(), Halt> {
+        let inst = match self.program.get(self.pc) {
+            Some(&inst) => inst,
+            None if self.pc == self.program.len() => return Err(Halt::Finished),
+            None => return Err(Halt::OutOfBounds),
         };
 
-        if is_jmp {
-            offset = offset.checked_add_signed(n).expect("offset under/overflow")
-        } else if is_nop {
-            offset += 1
-        } else {
-            debug_assert!(inst == "acc");
-            acc += n;
-            offset += 1
+        let next_pc = match inst {
+            Instruction::Acc(n) => {
+                self.acc += n;
+                self.pc + 1
+            }
+            Instruction::Jmp(n) => self.pc.checked_add_signed(n).ok_or(Halt::OutOfBounds)?,
+            Instruction::Nop(_) => self.pc + 1,
+            Instruction::Ext(op, n) => {
+                let offset = (op.exec)(&mut self.acc, n);
+                self.pc
+                    .checked_add_signed(offset)
+                    .ok_or(Halt::OutOfBounds)?
+            }
+        };
+
+        self.visited[self.pc] = true;
+        self.pc = next_pc;
+        self.steps += 1;
+        Ok(())
+    }
+
+    fn run(&mut self, conditions: HaltConditions) -> Halt {
+        self.run_with(conditions, |_, _| {})
+    }
+
+    /// Like `run`, calling `on_step` with the machine and the pc of each
+    /// instruction after executing it.
+    fn run_with(
+        &mut self,
+        conditions: HaltConditions,
+        mut on_step: impl FnMut(&Machine, usize),
+    ) -> Halt {
+        loop {
+            if conditions.detect_loops && self.visited.get(self.pc) == Some(&true) {
+                return Halt::Loop;
+            }
+            if conditions
+                .step_limit
+                .is_some_and(|limit| self.steps >= limit)
+            {
+                return Halt::StepLimit;
+            }
+
+            let (pc, acc) = (self.pc, self.acc);
+            if let Err(halt) = self.step() {
+                return halt;
+            }
+            on_step(self, pc);
+
+            if let Some(&bp) = conditions.breakpoints.iter().find(|bp| bp.hit(self, acc)) {
+                return Halt::Breakpoint(bp);
+            }
         }
+    }
+
+    fn state(&self) -> String {
+        let next = match self.program.get(self.pc) {
+            Some(inst) => inst.to_string(),
+            None if self.pc == self.program.len() => "<end>".to_string(),
+            None => "<out of bounds>".to_string(),
+        };
+        format!(
+            "pc={} acc={} steps={} next: {}",
+            self.pc, self.acc, self.steps, next
+        )
+    }
+}
 
-        if offset == data.len() {
-            return (acc, true);
+const DETECT_LOOPS: HaltConditions = HaltConditions {
+    detect_loops: true,
+    step_limit: None,
+    breakpoints: &[],
+};
+
+fn trace_line(machine: &Machine, pc: usize) -> String {
+    format!("{:>6} | {:>8} | {}", pc, machine.acc, machine.program[pc])
+}
+
+fn calculate_p1(data: &[Instruction]) -> isize {
+    let mut machine = Machine::new(data.to_vec());
+    machine.run(DETECT_LOOPS);
+    machine.acc
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Repair {
+    /// Instruction whose jmp/nop should be flipped.
+    pc: usize,
+    /// Accumulator value once the repaired program finishes.
+    acc: isize,
+}
+
+fn successor(pc: usize, inst: Instruction) -> Option<usize> {
+    match inst {
+        Instruction::Jmp(n) => pc.checked_add_signed(n),
+        _ => Some(pc + 1),
+    }
+}
+
+/// Finds every single jmp/nop flip which makes a looping program finish, in
+/// linear time.
+///
+/// Walking back from the exit through the control-flow graph gives the set of
+/// instructions which already reach the exit. A repair has to flip an
+/// instruction on the executed path, and is valid exactly when the flipped
+/// instruction jumps into that set: the path from there can't come back
+/// through the flipped instruction, or it would have reached the exit too.
+/// That only holds if the original program loops, so anything else is an
+/// error.
+fn find_repairs(program: &[Instruction]) -> Result<Vec<Repair>, String> {
+    if program
+        .iter()
+        .any(|inst| matches!(inst, Instruction::Ext(..)))
+    {
+        return Err("control-flow analysis doesn't support extended opcodes".to_string());
+    }
+    let len = program.len();
+
+    let mut predecessors: Vec<Vec<usize>> = vec![vec![]; len + 1];
+    for (pc, &inst) in program.iter().enumerate() {
+        if let Some(next) = successor(pc, inst).filter(|&next| next <= len) {
+            predecessors[next].push(pc);
         }
     }
-    (acc, false)
+
+    // Accumulator gained between each instruction and the exit, for the
+    // instructions which reach it.
+    let mut acc_to_exit: Vec<Option<isize>> = vec![None; len + 1];
+    acc_to_exit[len] = Some(0);
+    let mut queue = vec![len];
+    while let Some(next) = queue.pop() {
+        let acc = acc_to_exit[next].unwrap();
+        for &pc in &predecessors[next] {
+            let gained = match program[pc] {
+                Instruction::Acc(n) => n,
+                _ => 0,
+            };
+            acc_to_exit[pc] = Some(acc + gained);
+            queue.push(pc);
+        }
+    }
+
+    let mut repairs = vec![];
+    let mut visited = vec![false; len];
+    let mut pc = 0;
+    let mut acc = 0;
+    while pc < len && !visited[pc] {
+        visited[pc] = true;
+        let inst = program[pc];
+
+        if let Some(flipped) = inst.flipped() {
+            let exit_acc = successor(pc, flipped).and_then(|next| *acc_to_exit.get(next)?);
+            if let Some(exit_acc) = exit_acc {
+                repairs.push(Repair {
+                    pc,
+                    acc: acc + exit_acc,
+                });
+            }
+        }
+
+        if let Instruction::Acc(n) = inst {
+            acc += n;
+        }
+        match successor(pc, inst) {
+            Some(next) => pc = next,
+            None => return Err("program jumps out of bounds".to_string()),
+        }
+    }
+    if pc == len {
+        Err("program already terminates".to_string())
+    } else if pc > len {
+        Err("program jumps out of bounds".to_string())
+    } else {
+        Ok(repairs)
+    }
 }
 
-fn calculate_p1(data: &[(&str, isize)]) -> isize {
-    simulate(data, None).0
+fn calculate_p2(repairs: &[Repair]) -> isize {
+    repairs.first().expect("p2: no solution found").acc
 }
 
-fn calculate_p2(data: &[(&str, isize)]) -> isize {
-    for swap in 0..data.len() {
-        if data[swap].0 != "acc" {
-            let (acc_result, finished) = simulate(data, Some(swap));
+const DEBUG_HELP: &str = "commands:
+  s, step [n]           execute n instructions (default 1)
+  c, continue           run until a breakpoint, a loop or the program halts
+  l, loop               run until an instruction is about to repeat
+  b, break pc|acc <n>   stop when pc or acc becomes n
+  b, break              list breakpoints
+  d, delete <i>         delete breakpoint i
+  patch <pc> <inst>     replace an instruction, e.g. 'patch 7 nop -4'
+  p, print              show machine state
+  r, reset              restart from pc 0
+  q, quit";
+
+fn debug(machine: &mut Machine, input: impl BufRead, mut out: impl Write) -> io::Result<()> {
+    let mut breakpoints: Vec<Breakpoint> = vec![];
 
-            if finished {
-                return acc_result;
+    write!(out, "(dbg) ")?;
+    out.flush()?;
+    for line in input.lines() {
+        let line = line?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        let halt = match words.as_slice() {
+            [] => None,
+            ["s" | "step", n @ ..] => match n.first().map_or(Ok(1), |n| n.parse::<usize>()) {
+                Ok(n) => {
+                    let conditions = HaltConditions {
+                        step_limit: Some(machine.steps.saturating_add(n)),
+                        ..Default::default()
+                    };
+                    let mut trace = vec![];
+                    let halt = machine.run_with(conditions, |m, pc| trace.push(trace_line(m, pc)));
+                    for line in trace {
+                        writeln!(out, "{}", line)?;
+                    }
+                    Some(halt)
+                }
+                Err(_) => {
+                    writeln!(out, "invalid step count")?;
+                    None
+                }
+            },
+            ["c" | "continue"] => Some(machine.run(HaltConditions {
+                detect_loops: true,
+                breakpoints: &breakpoints,
+                ..Default::default()
+            })),
+            ["l" | "loop"] => Some(machine.run(DETECT_LOOPS)),
+            ["b" | "break"] => {
+                for (i, bp) in breakpoints.iter().enumerate() {
+                    writeln!(out, "{}: {:?}", i, bp)?;
+                }
+                None
+            }
+            ["b" | "break", kind, n] => {
+                let bp = match *kind {
+                    "pc" => n.parse().ok().map(Breakpoint::Pc),
+                    "acc" => n.parse().ok().map(Breakpoint::Acc),
+                    _ => None,
+                };
+                match bp {
+                    Some(bp) => {
+                        breakpoints.push(bp);
+                        writeln!(out, "{}: {:?}", breakpoints.len() - 1, bp)?;
+                    }
+                    None => writeln!(out, "usage: break pc|acc <n>")?,
+                }
+                None
+            }
+            ["d" | "delete", i] => {
+                match i.parse::<usize>() {
+                    Ok(i) if i < breakpoints.len() => {
+                        breakpoints.remove(i);
+                    }
+                    _ => writeln!(out, "no such breakpoint")?,
+                }
+                None
+            }
+            ["patch", pc, inst @ ..] => {
+                let pc = pc
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&pc| pc < machine.program.len());
+                match (pc, inst.join(" ").parse::<Instruction>()) {
+                    (Some(pc), Ok(inst)) => {
+                        let old = machine.patch(pc, inst);
+                        writeln!(out, "{}: {} -> {}", pc, old, inst)?;
+                    }
+                    (None, _) => writeln!(out, "invalid pc")?,
+                    (_, Err(e)) => writeln!(out, "invalid instruction: {}", e)?,
+                }
+                None
+            }
+            ["p" | "print"] => {
+                writeln!(out, "{}", machine.state())?;
+                None
             }
+            ["r" | "reset"] => {
+                machine.reset();
+                writeln!(out, "{}", machine.state())?;
+                None
+            }
+            ["q" | "quit"] => return Ok(()),
+            _ => {
+                writeln!(out, "{}", DEBUG_HELP)?;
+                None
+            }
+        };
+
+        match halt {
+            None | Some(Halt::StepLimit) => {}
+            Some(halt) => writeln!(out, "halted: {:?}", halt)?,
+        }
+        if halt.is_some() {
+            writeln!(out, "{}", machine.state())?;
         }
+        write!(out, "(dbg) ")?;
+        out.flush()?;
     }
-    panic!("p2: no solution found");
+    Ok(())
 }
 
 fn main() {
     let args = Cli::parse();
     let raw_inp = fs::read_to_string(args.input).expect("can't open input file");
-    let data = parse(&raw_inp);
+    let data = if args.assemble {
+        let extensions = if args.extended { EXTENDED_OPCODES } else { &[] };
+        assemble(&raw_inp, extensions).unwrap_or_else(|e| panic!("assembly failed: {}", e))
+    } else {
+        parse(&raw_inp)
+    };
+
+    if args.disassemble {
+        print!("{}", disassemble(&data));
+        return;
+    }
+    if args.trace {
+        let mut machine = Machine::new(data);
+        let halt = machine.run_with(DETECT_LOOPS, |m, pc| println!("{}", trace_line(m, pc)));
+        println!("halted: {:?}\n{}", halt, machine.state());
+        return;
+    }
+    if args.debug {
+        let mut machine = Machine::new(data);
+        debug(&mut machine, io::stdin().lock(), io::stdout()).expect("debugger io failed");
+        return;
+    }
+
     let p1 = calculate_p1(&data);
-    let p2 = calculate_p2(&data);
+    let repairs = match find_repairs(&data) {
+        Ok(repairs) => repairs,
+        Err(e) => {
+            println!("{}", p1);
+            eprintln!("p2 skipped: {}", e);
+            return;
+        }
+    };
+    let p2 = calculate_p2(&repairs);
     println!("{}\n{}", p1, p2);
+
+    if repairs.len() > 1 {
+        eprintln!("{} valid repairs:", repairs.len());
+        for repair in repairs {
+            eprintln!("  flip pc {} -> acc {}", repair.pc, repair.acc);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -88,6 +617,15 @@ mod tests {
     use super::*;
 
     const TEST_DATA: &str = include_str!("../../inputs/examples/2020_08");
+    const EXAMPLE: &str = "nop +0
+    acc +1
+    jmp +4
+    acc +3
+    jmp -3
+    acc -99
+    acc +1
+    jmp -4
+    acc +6";
     const REAL_DATA: &str = include_str!("../../inputs/real/2020_08");
 
     #[test]
@@ -97,7 +635,185 @@ mod tests {
 
     #[test]
     fn test_p2_example() {
-        assert_eq!(calculate_p2(&parse(TEST_DATA)), 8);
+        assert_eq!(calculate_p2(&find_repairs(&parse(TEST_DATA)).unwrap()), 8);
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_instruction() {
+        assert_eq!(
+            "mul +2".parse::<Instruction>(),
+            Err("unknown instruction 'mul'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_machine_halts() {
+        let mut machine = Machine::new(parse(EXAMPLE));
+        assert_eq!(machine.run(DETECT_LOOPS), Halt::Loop);
+        assert_eq!((machine.pc, machine.acc), (1, 5));
+
+        machine.reset();
+        let limit = HaltConditions {
+            step_limit: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(machine.run(limit), Halt::StepLimit);
+        assert_eq!((machine.pc, machine.steps), (6, 3));
+
+        machine.reset();
+        let original = machine.patch(0, Instruction::Jmp(-1));
+        assert_eq!(machine.run(DETECT_LOOPS), Halt::OutOfBounds);
+        machine.patch(0, original);
+
+        machine.reset();
+        machine.patch(7, Instruction::Nop(-4));
+        assert_eq!(machine.run(DETECT_LOOPS), Halt::Finished);
+        assert_eq!(machine.acc, 8);
+    }
+
+    #[test]
+    fn test_find_repairs_example() {
+        assert_eq!(
+            find_repairs(&parse(EXAMPLE)),
+            Ok(vec![Repair { pc: 7, acc: 8 }])
+        );
+    }
+
+    #[test]
+    fn test_find_repairs_multiple() {
+        let program = parse(
+            "nop +4
+            acc +1
+            jmp -2
+            acc +5
+            acc +2",
+        );
+        assert_eq!(
+            find_repairs(&program),
+            Ok(vec![Repair { pc: 0, acc: 2 }, Repair { pc: 2, acc: 8 }])
+        );
+
+        let mut machine = Machine::new(program.clone());
+        for repair in find_repairs(&program).unwrap() {
+            machine.reset();
+            let original = machine.patch(repair.pc, program[repair.pc].flipped().unwrap());
+            assert_eq!(machine.run(DETECT_LOOPS), Halt::Finished);
+            assert_eq!(machine.acc, repair.acc);
+            machine.patch(repair.pc, original);
+        }
+    }
+
+    #[test]
+    fn test_find_repairs_terminating() {
+        let program = parse(
+            "jmp +2
+            jmp -1
+            acc +1",
+        );
+        assert_eq!(
+            find_repairs(&program),
+            Err("program already terminates".to_string())
+        );
+        assert_eq!(
+            find_repairs(&parse("jmp -1")),
+            Err("program jumps out of bounds".to_string())
+        );
+    }
+
+    #[test]
+    fn test_debugger_session() {
+        let mut machine = Machine::new(parse(EXAMPLE));
+        let commands = "b pc 4\nc\ns 2\nl\nr\nb acc 2\nd 0\nc\npatch 7 nop -4\nc\ns 18446744073709551615\npatch 7 jmp -4\nr\nd 0\nc\nq\np\n";
+        let mut out = vec![];
+        debug(&mut machine, commands.as_bytes(), &mut out).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap().replace("(dbg) ", ""),
+            "0: Pc(4)
+halted: Breakpoint(Pc(4))
+pc=4 acc=5 steps=6 next: jmp -3
+     4 |        5 | jmp -3
+     1 |        6 | acc +1
+pc=2 acc=6 steps=8 next: jmp +4
+halted: Loop
+pc=2 acc=6 steps=8 next: jmp +4
+pc=0 acc=0 steps=0 next: nop +0
+1: Acc(2)
+halted: Breakpoint(Acc(2))
+pc=7 acc=2 steps=4 next: jmp -4
+7: jmp -4 -> nop -4
+halted: Finished
+pc=9 acc=8 steps=6 next: <end>
+halted: Finished
+pc=9 acc=8 steps=6 next: <end>
+7: nop -4 -> jmp -4
+pc=0 acc=0 steps=0 next: nop +0
+halted: Loop
+pc=1 acc=5 steps=7 next: acc +1
+"
+        );
+    }
+
+    #[test]
+    fn test_assemble_labels_and_comments() {
+        let src = "# the example program, with labels
+            nop +0
+            acc +1
+            jmp skip    ; forward reference
+        back:
+            acc +3
+            jmp -3
+            acc -99
+        skip: acc +1
+            jmp back
+            acc +6
+        ";
+        let program = assemble(src, &[]).unwrap();
+        assert_eq!(program, parse(EXAMPLE));
+        assert_eq!(
+            disassemble(&program),
+            "nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\njmp -4\nacc +6\n"
+        );
+        assert_eq!(parse(&disassemble(&program)), program);
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        assert_eq!(
+            assemble("jmp nowhere", &[]),
+            Err("line 1: unknown label 'nowhere'".to_string())
+        );
+        assert_eq!(
+            assemble("a: nop +0\na: nop +0", &[]),
+            Err("line 2: duplicate label 'a'".to_string())
+        );
+        assert_eq!(
+            assemble("nop +0\nmul +2", &[]),
+            Err("line 2: unknown instruction 'mul'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_extended_opcodes() {
+        let src = "
+            acc +2
+            mul +5
+            jnz done    ; skipped when acc is 0
+            acc +100
+        done:
+            acc +7
+        ";
+        let program = assemble(src, EXTENDED_OPCODES).unwrap();
+        assert_eq!(disassemble(&program[1..3]), "mul +5\njnz +2\n");
+
+        assert_eq!(
+            find_repairs(&program),
+            Err("control-flow analysis doesn't support extended opcodes".to_string())
+        );
+
+        let mut machine = Machine::new(program);
+        assert_eq!(machine.run(DETECT_LOOPS), Halt::Finished);
+        assert_eq!(machine.acc, 17);
     }
 
     #[test]
@@ -107,6 +823,6 @@ mod tests {
 
     #[test]
     fn test_p2_real() {
-        assert_eq!(calculate_p2(&parse(REAL_DATA)), 846);
+        assert_eq!(calculate_p2(&find_repairs(&parse(REAL_DATA)).unwrap()), 846);
     }
 }
@@ -1,14 +1,31 @@
+use ahash::AHashMap;
 use clap::Parser;
-use std::cmp::Ordering;
+use std::collections::VecDeque;
 use std::fs;
+use std::io::{self, BufRead};
 
 #[derive(Parser)]
 struct Cli {
-    #[clap(short, long)]
-    input: String,
+    #[clap(short, long, required_unless_present = "stream")]
+    input: Option<String>,
+
+    #[clap(long, default_value_t = 25, value_parser = parse_preamble)]
+    preamble: usize,
+
+    /// Print every invalid number with its index instead of the puzzle answers
+    #[clap(long)]
+    all_invalid: bool,
+
+    /// Read numbers from stdin line by line, printing invalid ones as they arrive
+    #[clap(long, conflicts_with = "input")]
+    stream: bool,
+
+    /// Also print every range summing to the invalid number
+    #[clap(long)]
+    ranges: bool,
 }
 
-fn parse(raw_inp: &str) -> Vec<u64> {
+fn parse(raw_inp: &str) -> Vec<i64> {
     raw_inp
         .trim()
         .split('\n')
@@ -16,58 +33,151 @@ fn parse(raw_inp: &str) -> Vec<u64> {
         .collect()
 }
 
-fn combination_adds_to(data: &[u64], target: u64) -> bool {
-    for i in data {
-        if i > &target {
-            continue;
-        } else if data.contains(&(target - i)) {
-            return true;
-        }
+/// A number can only be a sum of two others with at least two before it.
+fn parse_preamble(raw: &str) -> Result<usize, String> {
+    match raw.parse() {
+        Ok(n) if n >= 2 => Ok(n),
+        Ok(_) => Err("preamble must be at least 2".to_string()),
+        Err(e) => Err(e.to_string()),
     }
-    false
 }
 
-fn calculate_p1(data: &[u64], window_size: usize) -> u64 {
-    for i in window_size..data.len() {
-        if !combination_adds_to(&data[i - window_size..i], data[i]) {
-            return data[i];
+/// The last `size` numbers, with a count of each value so that membership
+/// checks are O(1) and validating a number is O(size).
+struct XmasWindow {
+    size: usize,
+    values: VecDeque<i64>,
+    counts: AHashMap<i64, usize>,
+}
+
+impl XmasWindow {
+    fn new(size: usize) -> Self {
+        XmasWindow {
+            size,
+            values: VecDeque::with_capacity(size + 1),
+            counts: AHashMap::with_capacity(size + 1),
         }
     }
-    panic!("p1: no solution");
-}
 
-fn calculate_p2(data: &[u64], target: u64) -> u64 {
-    let mut begin: usize = 0;
-    let mut end: usize = 0;
-    let mut sum: u64 = 0;
+    fn is_full(&self) -> bool {
+        self.values.len() == self.size
+    }
 
-    loop {
-        match sum.cmp(&target) {
-            Ordering::Less => {
-                sum += data[end];
-                end += 1;
-            }
-            Ordering::Greater => {
-                sum -= data[begin];
-                begin += 1;
-            }
-            Ordering::Equal => {
-                let slice = &data[begin..end];
-                let smallest = slice.iter().min().expect("empty slice (min)");
-                let biggest = slice.iter().max().expect("empty slice (max)");
-                return smallest + biggest;
+    fn is_valid(&self, target: i64) -> bool {
+        self.values.iter().any(|&i| {
+            // A number can only pair with its own value if that's in the
+            // window twice.
+            let needed = if target - i == i { 2 } else { 1 };
+            self.counts.get(&(target - i)).is_some_and(|&n| n >= needed)
+        })
+    }
+
+    fn push(&mut self, value: i64) {
+        self.values.push_back(value);
+        *self.counts.entry(value).or_default() += 1;
+
+        if self.values.len() > self.size {
+            let old = self.values.pop_front().unwrap();
+            let count = self.counts.get_mut(&old).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                self.counts.remove(&old);
             }
         }
     }
 }
 
+/// Index and value of every number which isn't the sum of two of the
+/// `preamble` numbers before it.
+fn invalid_numbers(
+    data: impl IntoIterator<Item = i64>,
+    preamble: usize,
+) -> impl Iterator<Item = (usize, i64)> {
+    let mut window = XmasWindow::new(preamble);
+    data.into_iter()
+        .enumerate()
+        .filter_map(move |(idx, value)| {
+            let invalid = window.is_full() && !window.is_valid(value);
+            window.push(value);
+            invalid.then_some((idx, value))
+        })
+}
+
+fn calculate_p1(data: &[i64], window_size: usize) -> i64 {
+    invalid_numbers(data.iter().copied(), window_size)
+        .next()
+        .expect("p1: no solution")
+        .1
+}
+
+/// Every contiguous run of at least two numbers which sums to `target`, as
+/// inclusive (start, end) indices ordered by end. Works on prefix sums, so
+/// negative numbers are fine.
+fn contiguous_ranges(data: &[i64], target: i64) -> Vec<(usize, usize)> {
+    let mut prefix = vec![0; data.len() + 1];
+    for (idx, value) in data.iter().enumerate() {
+        prefix[idx + 1] = prefix[idx] + value;
+    }
+
+    let mut starts: AHashMap<i64, Vec<usize>> = AHashMap::default();
+    let mut ranges = vec![];
+    for end in 1..data.len() {
+        starts.entry(prefix[end - 1]).or_default().push(end - 1);
+        if let Some(matching) = starts.get(&(prefix[end + 1] - target)) {
+            ranges.extend(matching.iter().map(|&start| (start, end)));
+        }
+    }
+    ranges
+}
+
+fn weakness(data: &[i64], (start, end): (usize, usize)) -> i64 {
+    let slice = &data[start..=end];
+    slice.iter().min().unwrap() + slice.iter().max().unwrap()
+}
+
+fn calculate_p2(data: &[i64], target: i64) -> Result<i64, String> {
+    contiguous_ranges(data, target)
+        .into_iter()
+        .min()
+        .map(|range| weakness(data, range))
+        .ok_or_else(|| format!("no range of two or more numbers sums to {}", target))
+}
+
 fn main() {
     let args = Cli::parse();
-    let raw_inp = fs::read_to_string(args.input).expect("can't open input file");
+
+    if args.stream {
+        let numbers = io::stdin()
+            .lock()
+            .lines()
+            .map(|line| line.expect("can't read stdin"))
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.trim().parse().expect("parse as int failed"));
+        for (idx, value) in invalid_numbers(numbers, args.preamble) {
+            println!("{}: {}", idx, value);
+        }
+        return;
+    }
+
+    let raw_inp = fs::read_to_string(args.input.unwrap()).expect("can't open input file");
     let data = parse(&raw_inp);
-    let p1 = calculate_p1(&data, 25);
-    let p2 = calculate_p2(&data, p1);
+
+    if args.all_invalid {
+        for (idx, value) in invalid_numbers(data, args.preamble) {
+            println!("{}: {}", idx, value);
+        }
+        return;
+    }
+
+    let p1 = calculate_p1(&data, args.preamble);
+    let p2 = calculate_p2(&data, p1).unwrap_or_else(|e| panic!("p2: {}", e));
     println!("{}\n{}", p1, p2);
+
+    if args.ranges {
+        for range in contiguous_ranges(&data, p1) {
+            println!("{}..={}: {}", range.0, range.1, weakness(&data, range));
+        }
+    }
 }
 
 #[cfg(test)]
@@ -84,7 +194,69 @@ mod tests {
 
     #[test]
     fn test_p2_example() {
-        assert_eq!(calculate_p2(&parse(&TEST_DATA), 127), 62);
+        assert_eq!(calculate_p2(&parse(TEST_DATA), 127), Ok(62));
+    }
+
+    #[test]
+    fn test_invalid_numbers_example() {
+        let invalid: Vec<(usize, i64)> = invalid_numbers(parse(TEST_DATA), 5).collect();
+        assert_eq!(invalid, vec![(14, 127)]);
+
+        let invalid: Vec<(usize, i64)> = invalid_numbers(parse(TEST_DATA), 2).collect();
+        assert_eq!(invalid[..3], [(2, 15), (3, 25), (4, 47)]);
+    }
+
+    #[test]
+    fn test_window_evicts_oldest() {
+        let mut window = XmasWindow::new(2);
+        for value in [1, 1, 5] {
+            window.push(value);
+        }
+        assert!(window.is_valid(6));
+        assert!(!window.is_valid(7));
+        assert_eq!(window.values, [1, 5]);
+        assert_eq!(window.counts[&1], 1);
+    }
+
+    #[test]
+    fn test_window_pairs_need_two_numbers() {
+        let mut window = XmasWindow::new(4);
+        for value in [5, 1, 2] {
+            window.push(value);
+        }
+        assert!(!window.is_valid(10));
+        assert!(window.is_valid(6));
+
+        window.push(5);
+        assert!(window.is_valid(10));
+    }
+
+    #[test]
+    fn test_contiguous_ranges_signed() {
+        let data = [5, -2, 7, 3, -1, 4];
+        assert_eq!(contiguous_ranges(&data, 8), vec![(1, 3)]);
+        assert_eq!(contiguous_ranges(&data, 10), vec![(0, 2), (2, 3)]);
+        assert_eq!(calculate_p2(&data, 10), Ok(5));
+    }
+
+    #[test]
+    fn test_no_range() {
+        assert_eq!(contiguous_ranges(&[10, 1, 2], 10), vec![]);
+        assert_eq!(
+            calculate_p2(&[10, 1, 2], 10),
+            Err("no range of two or more numbers sums to 10".to_string())
+        );
+        assert_eq!(contiguous_ranges(&[], 0), vec![]);
+    }
+
+    #[test]
+    fn test_parse_preamble() {
+        assert_eq!(parse_preamble("2"), Ok(2));
+        assert_eq!(
+            parse_preamble("0"),
+            Err("preamble must be at least 2".to_string())
+        );
+        assert!(parse_preamble("-1").is_err());
     }
 
     #[test]
@@ -94,6 +266,6 @@ mod tests {
 
     #[test]
     fn test_p2_real() {
-        assert_eq!(calculate_p2(&parse(&REAL_DATA), 10884537), 1261309);
+        assert_eq!(calculate_p2(&parse(REAL_DATA), 10884537), Ok(1261309));
     }
 }
@@ -1,11 +1,65 @@
-use ahash::{AHashMap, AHashSet};
+use advent_of_code_2020::Rng;
+use ahash::AHashSet;
 use clap::Parser;
+use std::collections::BTreeMap;
 use std::fs;
 
 #[derive(Parser)]
 struct Cli {
     #[clap(short, long)]
     input: String,
+
+    /// Largest joltage increase an adapter (or the device) accepts
+    #[clap(long, default_value_t = 3)]
+    max_step: u64,
+
+    /// How far above the highest adapter the device is rated
+    #[clap(long, default_value_t = 3)]
+    device_offset: u64,
+
+    /// Count arrangements modulo this number (not with --list, --nth or --sample)
+    #[clap(
+        long = "mod",
+        value_parser = parse_modulus,
+        conflicts_with_all = ["list", "nth", "sample"]
+    )]
+    modulus: Option<u128>,
+
+    /// Also print the count of every joltage difference in the chain
+    #[clap(long)]
+    histogram: bool,
+
+    /// Print the first N arrangements in lexicographic order
+    #[clap(long)]
+    list: Option<usize>,
+
+    /// Print the arrangement at this (0-based) lexicographic index
+    #[clap(long)]
+    nth: Option<u128>,
+
+    /// Print N arrangements picked uniformly at random
+    #[clap(long)]
+    sample: Option<usize>,
+
+    #[clap(long, default_value_t = 0, requires = "sample")]
+    seed: u64,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct ChainConfig {
+    max_step: u64,
+    device_offset: u64,
+    modulus: Option<u128>,
+}
+
+impl Default for ChainConfig {
+    fn default() -> Self {
+        ChainConfig {
+            max_step: 3,
+            device_offset: 3,
+            modulus: None,
+        }
+    }
 }
 
 fn parse(raw_inp: &str) -> AHashSet<u64> {
@@ -16,54 +70,313 @@ fn parse(raw_inp: &str) -> AHashSet<u64> {
         .collect()
 }
 
-fn calculate_p1(data: &AHashSet<u64>) -> u64 {
-    let mut joltage: u64 = 0;
-    let mut increases: [u64; 3] = [0, 0, 0];
+fn parse_modulus(raw: &str) -> Result<u128, String> {
+    match raw.parse() {
+        Ok(m) if m >= 2 => Ok(m),
+        Ok(_) => Err("modulus must be at least 2".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// `(a + b) % m` for `a` and `b` already below `m`, without overflowing.
+fn add_mod(a: u128, b: u128, m: u128) -> u128 {
+    if a >= m - b {
+        a - (m - b)
+    } else {
+        a + b
+    }
+}
 
-    let mut any_changed = true;
-    while any_changed {
-        any_changed = false;
-        for increase in 1..4 {
-            if data.contains(&(joltage + increase)) {
-                joltage += increase;
-                increases[(increase - 1) as usize] += 1;
-                any_changed = true;
+/// Joltages along the full chain: the outlet, every adapter, then the device.
+fn chain(data: &AHashSet<u64>, config: &ChainConfig) -> Vec<u64> {
+    let mut joltages: Vec<u64> = data.iter().copied().collect();
+    joltages.push(0);
+    joltages.sort_unstable();
+    joltages.push(joltages.last().unwrap() + config.device_offset);
+    joltages
+}
+
+/// Count of each joltage difference when every adapter is used. Fails if
+/// some link in the chain isn't 1..=max_step jolts.
+fn jolt_differences(
+    data: &AHashSet<u64>,
+    config: &ChainConfig,
+) -> Result<BTreeMap<u64, u64>, String> {
+    let mut histogram = BTreeMap::new();
+
+    for pair in chain(data, config).windows(2) {
+        let diff = pair[1] - pair[0];
+        if !(1..=config.max_step).contains(&diff) {
+            return Err(format!(
+                "can't connect {} jolts to {} jolts (max step {})",
+                pair[0], pair[1], config.max_step
+            ));
+        }
+        *histogram.entry(diff).or_default() += 1;
+    }
+    Ok(histogram)
+}
+
+const OVERFLOW: &str = "arrangement count overflows u128";
+
+/// Number of ways to get from the first of the sorted `joltages` to each of
+/// them, optionally modulo `modulus`.
+fn ways_to_reach(
+    joltages: &[u64],
+    max_step: u64,
+    modulus: Option<u128>,
+) -> Result<Vec<u128>, String> {
+    let mut ways: Vec<u128> = vec![0; joltages.len()];
+    ways[0] = 1;
+
+    for i in 1..joltages.len() {
+        if joltages[i] - joltages[i - 1] > max_step {
+            return Err(format!(
+                "nothing within {} jolts below {} jolts",
+                max_step, joltages[i]
+            ));
+        }
+        for j in (0..i).rev() {
+            if joltages[i] - joltages[j] > max_step {
                 break;
             }
+            ways[i] = match modulus {
+                Some(m) => add_mod(ways[i], ways[j], m),
+                None => ways[i].checked_add(ways[j]).ok_or(OVERFLOW)?,
+            };
         }
     }
+    Ok(ways)
+}
 
-    increases[0] * (increases[2] + 1)
+/// Number of ways to get from the outlet to the device, optionally modulo
+/// `config.modulus`. Fails if there are none or the count overflows.
+fn count_arrangements(data: &AHashSet<u64>, config: &ChainConfig) -> Result<u128, String> {
+    if config.modulus.is_some_and(|m| m < 2) {
+        return Err("modulus must be at least 2".to_string());
+    }
+    match ways_to_reach(&chain(data, config), config.max_step, config.modulus) {
+        Ok(ways) => Ok(*ways.last().unwrap()),
+        Err(e) if e == OVERFLOW => Err(format!("{}, use --mod", e)),
+        Err(e) => Err(e),
+    }
 }
 
-fn calculate_p2(data: &AHashSet<u64>) -> u64 {
-    let mut ways: AHashMap<u64, u64> = AHashMap::default();
-    ways.insert(0, 1);
+/// Arrangements are the adapters used on the way from the outlet to the
+/// device, ordered lexicographically by joltage (so stopping at the device is
+/// ordered after moving on to any adapter).
+struct Arrangements {
+    joltages: Vec<u64>,
+    max_step: u64,
+}
+
+impl Arrangements {
+    fn new(data: &AHashSet<u64>, config: &ChainConfig) -> Result<Self, String> {
+        let joltages = chain(data, config);
+        if let Some(pair) = joltages
+            .windows(2)
+            .find(|pair| pair[1] - pair[0] > config.max_step)
+        {
+            return Err(format!(
+                "nothing within {} jolts above {} jolts",
+                config.max_step, pair[0]
+            ));
+        }
+
+        Ok(Arrangements {
+            joltages,
+            max_step: config.max_step,
+        })
+    }
 
-    let max_joltage: u64 = *data.iter().max().expect("no data");
+    /// Counts the arrangements from each point in the chain, which picking
+    /// arrangements by index needs but listing them doesn't. Fails if there
+    /// are too many to count.
+    fn indexed(&self) -> Result<IndexedArrangements<'_>, String> {
+        // Counting paths to the outlet along the mirrored chain gives the
+        // number of paths from each point to the device.
+        let top = *self.joltages.last().unwrap();
+        let mirrored: Vec<u64> = self.joltages.iter().rev().map(|j| top - j).collect();
+        let mut paths_from = ways_to_reach(&mirrored, self.max_step, None)?;
+        paths_from.reverse();
 
-    for joltage in 1..max_joltage + 1 {
-        let mut w = 0;
-        if data.contains(&(joltage)) {
-            for diff in 1..4 {
-                if joltage >= diff {
-                    w += ways.get(&(joltage - diff)).unwrap_or(&0);
+        Ok(IndexedArrangements {
+            arrangements: self,
+            paths_from,
+        })
+    }
+
+    fn adapters(&self, path: &[usize]) -> Vec<u64> {
+        path.iter().map(|&i| self.joltages[i]).collect()
+    }
+
+    fn iter(&self) -> ArrangementIter<'_> {
+        ArrangementIter {
+            arrangements: self,
+            path: vec![],
+            done: false,
+        }
+    }
+}
+
+struct IndexedArrangements<'a> {
+    arrangements: &'a Arrangements,
+    /// Number of arrangements from each point in the chain to the device.
+    paths_from: Vec<u128>,
+}
+
+impl IndexedArrangements<'_> {
+    fn count(&self) -> u128 {
+        self.paths_from[0]
+    }
+
+    fn nth(&self, mut k: u128) -> Option<Vec<u64>> {
+        if k >= self.count() {
+            return None;
+        }
+
+        let joltages = &self.arrangements.joltages;
+        let device = joltages.len() - 1;
+        let mut path = vec![];
+        let mut i = 0;
+        while i != device {
+            for j in successors(joltages, self.arrangements.max_step, i) {
+                if k < self.paths_from[j] {
+                    i = j;
+                    break;
                 }
+                k -= self.paths_from[j];
+            }
+            path.push(i);
+        }
+        path.pop();
+        Some(self.arrangements.adapters(&path))
+    }
+
+    fn sample(&self, rng: &mut Rng) -> Vec<u64> {
+        self.nth(rng.below(self.count())).unwrap()
+    }
+}
+
+fn successors(joltages: &[u64], max_step: u64, i: usize) -> impl Iterator<Item = usize> + '_ {
+    (i + 1..joltages.len()).take_while(move |&j| joltages[j] - joltages[i] <= max_step)
+}
+
+/// Depth-first walk through the chain, always trying the lowest next adapter
+/// first.
+struct ArrangementIter<'a> {
+    arrangements: &'a Arrangements,
+    /// Indices of the current arrangement's joltages, from the outlet to the
+    /// device.
+    path: Vec<usize>,
+    done: bool,
+}
+
+impl ArrangementIter<'_> {
+    fn descend(&mut self) {
+        let device = self.arrangements.joltages.len() - 1;
+        while *self.path.last().unwrap() != device {
+            self.path.push(self.path.last().unwrap() + 1);
+        }
+    }
+
+    fn backtrack(&mut self) -> bool {
+        let joltages = &self.arrangements.joltages;
+        while self.path.len() >= 2 {
+            let last = self.path.pop().unwrap();
+            let prev = *self.path.last().unwrap();
+            if last + 1 < joltages.len()
+                && joltages[last + 1] - joltages[prev] <= self.arrangements.max_step
+            {
+                self.path.push(last + 1);
+                self.descend();
+                return true;
             }
         }
-        ways.insert(joltage, w);
+        false
     }
+}
+
+impl Iterator for ArrangementIter<'_> {
+    type Item = Vec<u64>;
 
-    *ways.get(&max_joltage).expect("p2 calculation failed")
+    fn next(&mut self) -> Option<Vec<u64>> {
+        if self.done {
+            return None;
+        }
+        if self.path.is_empty() {
+            self.path.push(0);
+            self.descend();
+        } else if !self.backtrack() {
+            self.done = true;
+            return None;
+        }
+        let path = &self.path[1..self.path.len() - 1];
+        Some(self.arrangements.adapters(path))
+    }
+}
+
+fn format_arrangement(arrangement: &[u64]) -> String {
+    arrangement
+        .iter()
+        .map(|j| j.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn calculate_p1(data: &AHashSet<u64>, config: &ChainConfig) -> u64 {
+    let histogram = jolt_differences(data, config).unwrap_or_else(|e| panic!("p1: {}", e));
+    histogram.get(&1).unwrap_or(&0) * histogram.get(&3).unwrap_or(&0)
+}
+
+fn calculate_p2(data: &AHashSet<u64>, config: &ChainConfig) -> u128 {
+    count_arrangements(data, config).unwrap_or_else(|e| panic!("p2: {}", e))
 }
 
 fn main() {
     let args = Cli::parse();
     let raw_inp = fs::read_to_string(args.input).expect("can't open input file");
     let data = parse(&raw_inp);
-    let p1 = calculate_p1(&data);
-    let p2 = calculate_p2(&data);
+    let config = ChainConfig {
+        max_step: args.max_step,
+        device_offset: args.device_offset,
+        modulus: args.modulus,
+    };
+
+    if args.list.is_some() || args.nth.is_some() || args.sample.is_some() {
+        let arrangements = Arrangements::new(&data, &config).unwrap_or_else(|e| panic!("{}", e));
+
+        for arrangement in arrangements.iter().take(args.list.unwrap_or(0)) {
+            println!("{}", format_arrangement(&arrangement));
+        }
+        if args.nth.is_none() && args.sample.is_none() {
+            return;
+        }
+
+        let indexed = arrangements.indexed().unwrap_or_else(|e| panic!("{}", e));
+        if let Some(k) = args.nth {
+            match indexed.nth(k) {
+                Some(arrangement) => println!("{}", format_arrangement(&arrangement)),
+                None => panic!("only {} arrangements", indexed.count()),
+            }
+        }
+        let mut rng = Rng::new(args.seed);
+        for _ in 0..args.sample.unwrap_or(0) {
+            println!("{}", format_arrangement(&indexed.sample(&mut rng)));
+        }
+        return;
+    }
+
+    let p1 = calculate_p1(&data, &config);
+    let p2 = calculate_p2(&data, &config);
     println!("{}\n{}", p1, p2);
+
+    if args.histogram {
+        let histogram = jolt_differences(&data, &config).unwrap();
+        for (diff, count) in histogram {
+            println!("{}: {}", diff, count);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -80,11 +393,17 @@ mod tests {
     #[test]
     fn test_p1_examples() {
         assert_eq!(
-            calculate_p1(&AHashSet::from_iter(TEST_DATA_1.into_iter())),
+            calculate_p1(
+                &AHashSet::from_iter(TEST_DATA_1.into_iter()),
+                &ChainConfig::default()
+            ),
             35
         );
         assert_eq!(
-            calculate_p1(&AHashSet::from_iter(TEST_DATA_2.into_iter())),
+            calculate_p1(
+                &AHashSet::from_iter(TEST_DATA_2.into_iter()),
+                &ChainConfig::default()
+            ),
             220
         );
     }
@@ -92,22 +411,204 @@ mod tests {
     #[test]
     fn test_p2_examples() {
         assert_eq!(
-            calculate_p2(&AHashSet::from_iter(TEST_DATA_1.into_iter())),
+            calculate_p2(
+                &AHashSet::from_iter(TEST_DATA_1.into_iter()),
+                &ChainConfig::default()
+            ),
             8
         );
         assert_eq!(
-            calculate_p2(&AHashSet::from_iter(TEST_DATA_2.into_iter())),
+            calculate_p2(
+                &AHashSet::from_iter(TEST_DATA_2.into_iter()),
+                &ChainConfig::default()
+            ),
             19208
         );
     }
 
+    #[test]
+    fn test_jolt_differences() {
+        let data = AHashSet::from_iter(TEST_DATA_1);
+        assert_eq!(
+            jolt_differences(&data, &ChainConfig::default()),
+            Ok(BTreeMap::from([(1, 7), (3, 5)]))
+        );
+
+        // Only the differences in the chain are counted, however large the
+        // largest allowed one.
+        let config = ChainConfig {
+            max_step: u64::MAX,
+            ..Default::default()
+        };
+        assert_eq!(
+            jolt_differences(&data, &config),
+            Ok(BTreeMap::from([(1, 7), (3, 5)]))
+        );
+
+        let config = ChainConfig {
+            max_step: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            jolt_differences(&data, &config),
+            Err("can't connect 1 jolts to 4 jolts (max step 2)".to_string())
+        );
+    }
+
+    #[test]
+    fn test_count_arrangements_config() {
+        let data = AHashSet::from_iter(TEST_DATA_2);
+        let config = ChainConfig {
+            modulus: Some(1000),
+            ..Default::default()
+        };
+        assert_eq!(count_arrangements(&data, &config), Ok(208));
+
+        // With the device 1 jolt up, it can take any of the adapters.
+        let config = ChainConfig {
+            device_offset: 1,
+            ..Default::default()
+        };
+        let small = AHashSet::from_iter([1, 2, 3]);
+        assert_eq!(count_arrangements(&small, &ChainConfig::default()), Ok(4));
+        assert_eq!(count_arrangements(&small, &config), Ok(7));
+
+        let config = ChainConfig {
+            max_step: 4,
+            device_offset: 5,
+            modulus: None,
+        };
+        assert_eq!(
+            count_arrangements(&data, &config),
+            Err("nothing within 4 jolts below 54 jolts".to_string())
+        );
+    }
+
+    #[test]
+    fn test_count_arrangements_overflow() {
+        let data: AHashSet<u64> = (1..200).collect();
+        assert_eq!(
+            count_arrangements(&data, &ChainConfig::default()),
+            Err("arrangement count overflows u128, use --mod".to_string())
+        );
+
+        let config = ChainConfig {
+            modulus: Some(1_000_000_007),
+            ..Default::default()
+        };
+        assert!(count_arrangements(&data, &config).is_ok());
+
+        // Moduli this big would overflow adding two counts before reducing.
+        for (m, expected) in [
+            (u128::MAX, 26500261363488977584209273725966716105),
+            (u128::MAX - 1, 26500261363488977584209357804142475526),
+        ] {
+            let config = ChainConfig {
+                modulus: Some(m),
+                ..Default::default()
+            };
+            assert_eq!(count_arrangements(&data, &config), Ok(expected));
+        }
+
+        let config = ChainConfig {
+            modulus: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            count_arrangements(&data, &config),
+            Err("modulus must be at least 2".to_string())
+        );
+        assert!(parse_modulus("1").is_err());
+        assert_eq!(parse_modulus("2"), Ok(2));
+    }
+
+    #[test]
+    fn test_arrangements_in_order() {
+        let data = AHashSet::from_iter(TEST_DATA_1);
+        let arrangements = Arrangements::new(&data, &ChainConfig::default()).unwrap();
+        let listed: Vec<Vec<u64>> = arrangements.iter().collect();
+        let indexed = arrangements.indexed().unwrap();
+
+        assert_eq!(
+            listed,
+            vec![
+                vec![1, 4, 5, 6, 7, 10, 11, 12, 15, 16, 19],
+                vec![1, 4, 5, 6, 7, 10, 12, 15, 16, 19],
+                vec![1, 4, 5, 7, 10, 11, 12, 15, 16, 19],
+                vec![1, 4, 5, 7, 10, 12, 15, 16, 19],
+                vec![1, 4, 6, 7, 10, 11, 12, 15, 16, 19],
+                vec![1, 4, 6, 7, 10, 12, 15, 16, 19],
+                vec![1, 4, 7, 10, 11, 12, 15, 16, 19],
+                vec![1, 4, 7, 10, 12, 15, 16, 19],
+            ]
+        );
+        for (k, arrangement) in listed.iter().enumerate() {
+            assert_eq!(indexed.nth(k as u128).as_ref(), Some(arrangement));
+        }
+        assert_eq!(indexed.nth(8), None);
+    }
+
+    #[test]
+    fn test_nth_arrangement_matches_iter() {
+        let data = AHashSet::from_iter(TEST_DATA_2);
+        let arrangements = Arrangements::new(&data, &ChainConfig::default()).unwrap();
+        let indexed = arrangements.indexed().unwrap();
+        assert_eq!(indexed.count(), 19208);
+
+        let mut iter = arrangements.iter();
+        assert_eq!(iter.next(), indexed.nth(0));
+        assert_eq!(iter.nth(999), indexed.nth(1000));
+        assert_eq!(iter.last(), indexed.nth(19207));
+    }
+
+    #[test]
+    fn test_list_arrangements_beyond_u128() {
+        // Far too many arrangements to count, but listing them is fine.
+        let data: AHashSet<u64> = (1..200).collect();
+        let arrangements = Arrangements::new(&data, &ChainConfig::default()).unwrap();
+        assert!(arrangements.indexed().is_err());
+
+        let mut iter = arrangements.iter();
+        assert_eq!(iter.next(), Some((1..200).collect()));
+        assert_eq!(iter.next().unwrap().len(), 198);
+    }
+
+    #[test]
+    fn test_sample_arrangements() {
+        let data = AHashSet::from_iter(TEST_DATA_1);
+        let arrangements = Arrangements::new(&data, &ChainConfig::default()).unwrap();
+        let listed: Vec<Vec<u64>> = arrangements.iter().collect();
+        let arrangements = arrangements.indexed().unwrap();
+
+        let mut rng = Rng::new(42);
+        let mut seen = [0; 8];
+        for _ in 0..8000 {
+            let sample = arrangements.sample(&mut rng);
+            seen[listed.iter().position(|a| a == &sample).unwrap()] += 1;
+        }
+        assert!(seen.iter().all(|&n| (800..1200).contains(&n)), "{:?}", seen);
+
+        let mut rng_1 = Rng::new(7);
+        let mut rng_2 = Rng::new(7);
+        assert_eq!(
+            arrangements.sample(&mut rng_1),
+            arrangements.sample(&mut rng_2)
+        );
+    }
+
     #[test]
     fn test_p1_real() {
-        assert_eq!(calculate_p1(&parse(REAL_DATA)), 2240);
+        assert_eq!(
+            calculate_p1(&parse(REAL_DATA), &ChainConfig::default()),
+            2240
+        );
     }
 
     #[test]
     fn test_p2_real() {
-        assert_eq!(calculate_p2(&parse(REAL_DATA)), 99214346656768);
+        assert_eq!(
+            calculate_p2(&parse(REAL_DATA), &ChainConfig::default()),
+            99214346656768
+        );
     }
 }
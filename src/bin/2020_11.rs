@@ -1,12 +1,58 @@
-use clap::Parser;
+use advent_of_code_2020::Rng;
+use ahash::{AHashMap, AHasher};
+use clap::{ArgGroup, Parser, ValueEnum};
 use ndarray::{Array2, Zip};
 use rayon::prelude::*;
+use std::fmt;
 use std::fs;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
 
 #[derive(Parser)]
+#[clap(group(ArgGroup::new("simulation").args(["rules", "evolution"]).multiple(true)))]
 struct Cli {
-    #[clap(short, long)]
-    input: String,
+    #[clap(short, long, required_unless_present = "generate")]
+    input: Option<String>,
+
+    /// Solve both puzzles on bitboards, which is much faster for big layouts
+    #[clap(long, conflicts_with_all = ["rules", "evolution"])]
+    bitboard: bool,
+
+    /// Print a random square layout of this size instead of solving anything
+    #[clap(long, conflicts_with = "input")]
+    generate: Option<usize>,
+
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Rule file to simulate instead of the two seating puzzles
+    #[clap(long)]
+    rules: Option<String>,
+
+    /// Give up on rule files which haven't settled after this many generations
+    #[clap(long, requires = "simulation")]
+    max_steps: Option<usize>,
+
+    /// Write every generation of the layout to the output file
+    #[clap(long, value_enum, requires = "output")]
+    evolution: Option<EvolutionFormat>,
+
+    #[clap(long)]
+    output: Option<String>,
+
+    /// Which puzzle's rules to use with --evolution when there's no rule file
+    #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=2))]
+    part: u8,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum EvolutionFormat {
+    /// Every layout as text, with its generation and number of flipped cells
+    Text,
+    /// Animated GIF with one frame per generation
+    Gif,
+    /// Binary log of the initial layout followed by the cells which change
+    Delta,
 }
 
 fn parse(raw_inp: &str) -> Array2<u8> {
@@ -28,130 +74,1004 @@ const DIRECTIONS: [(isize, isize); 8] = [
     (1, 1),
 ];
 
-const FLOOR: u8 = b'.';
-const EMPTY: u8 = b'L';
 const OCCUPIED: u8 = b'#';
 
-fn validate_seat_offset(
-    data: &Array2<u8>,
-    x: usize,
-    y: usize,
-    x_offset: isize,
-    y_offset: isize,
-) -> Option<(usize, usize)> {
-    let new_x = x.checked_add_signed(x_offset)?;
-    let new_y = y.checked_add_signed(y_offset)?;
-
-    if data.get((new_y, new_x)).is_some() {
-        return Some((new_y, new_x));
-    }
-    None
-}
-
-fn visible_occupied_seats_p1(data: &Array2<u8>, x: usize, y: usize) -> Vec<(usize, usize)> {
-    DIRECTIONS
-        .iter()
-        .filter_map(|&(xd, yd)| validate_seat_offset(data, x, y, xd, yd))
-        .collect()
-}
-
-fn visible_occupied_seats_p2(data: &Array2<u8>, x: usize, y: usize) -> Vec<(usize, usize)> {
-    DIRECTIONS
-        .iter()
-        .filter_map(|&(xd, yd)| {
-            let mut distance = 1;
-            while let Some(coord) = validate_seat_offset(data, x, y, xd * distance, yd * distance) {
-                if data[coord] == FLOOR {
-                    distance += 1;
-                } else {
-                    return Some(coord);
+const SEATING_P1: &str = "states .L#
+L -> # if # == 0
+# -> L if # >= 4";
+
+const SEATING_P2: &str = "states .L#
+line-of-sight .
+L -> # if # == 0
+# -> L if # >= 5";
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Convergence {
+    /// The layout stopped changing after this many generations.
+    Stable(usize),
+    /// Generation `start + length` is the same as generation `start`.
+    Cycle { start: usize, length: usize },
+    /// Neither of the above within the step limit.
+    StepLimit(usize),
+}
+
+impl fmt::Display for Convergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Convergence::Stable(generations) => {
+                write!(f, "stable after {} generations", generations)
+            }
+            Convergence::Cycle { start, length } => write!(
+                f,
+                "cycle of length {} starting at generation {}",
+                length, start
+            ),
+            Convergence::StepLimit(generations) => {
+                write!(f, "still changing after {} generations", generations)
+            }
+        }
+    }
+}
+
+/// The puzzles only have an answer once the layout stops changing.
+fn settled_count(count: usize, convergence: Convergence) -> Result<usize, String> {
+    match convergence {
+        Convergence::Stable(_) => Ok(count),
+        _ => Err(format!("layout never settles, {}", convergence)),
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A cell in state `from` becomes `to` if the number of its neighbours in
+/// state `counted` compares to `n` as `cmp` says.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Rule {
+    from: u8,
+    to: u8,
+    counted: u8,
+    cmp: Comparison,
+    n: usize,
+}
+
+impl Rule {
+    fn applies(&self, count: usize) -> bool {
+        match self.cmp {
+            Comparison::Eq => count == self.n,
+            Comparison::Ne => count != self.n,
+            Comparison::Lt => count < self.n,
+            Comparison::Le => count <= self.n,
+            Comparison::Gt => count > self.n,
+            Comparison::Ge => count >= self.n,
+        }
+    }
+}
+
+/// A set of cell states, how to find each cell's neighbours and the rules to
+/// apply every generation.
+///
+/// Parsed from a rule file, one directive per line (`//` starts a comment):
+///
+/// ```text
+/// states .L#              every state a cell may be in
+/// offsets -1,0 1,0 0,1    neighbour directions as x,y (default: all 8 adjacent)
+/// line-of-sight .         look past cells in these states (default: nearest cell only)
+/// wrap                    neighbours wrap around the edges of the layout
+/// L -> # if # == 0        rules, first match wins (==, !=, <, <=, >, >=)
+/// ```
+#[derive(Debug, Clone)]
+struct Automaton {
+    states: Vec<u8>,
+    offsets: Vec<(isize, isize)>,
+    /// States which line-of-sight looks through, if enabled. These are taken
+    /// from the initial layout, so no rule can change a cell into or out of
+    /// one of them.
+    transparent: Option<Vec<u8>>,
+    wrap: bool,
+    rules: Vec<Rule>,
+}
+
+fn single_state(s: &str, states: &[u8]) -> Result<u8, String> {
+    match s.as_bytes() {
+        &[b] if states.contains(&b) => Ok(b),
+        _ => Err(format!("unknown state '{}'", s)),
+    }
+}
+
+fn parse_rule(line: &str, states: &[u8]) -> Result<Rule, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let &[from, "->", to, "if", counted, cmp, n] = words.as_slice() else {
+        return Err(format!("can't parse rule '{}'", line));
+    };
+
+    let cmp = match cmp {
+        "==" => Comparison::Eq,
+        "!=" => Comparison::Ne,
+        "<" => Comparison::Lt,
+        "<=" => Comparison::Le,
+        ">" => Comparison::Gt,
+        ">=" => Comparison::Ge,
+        _ => return Err(format!("unknown comparison '{}'", cmp)),
+    };
+
+    Ok(Rule {
+        from: single_state(from, states)?,
+        to: single_state(to, states)?,
+        counted: single_state(counted, states)?,
+        cmp,
+        n: n.parse().or(Err("can't parse count"))?,
+    })
+}
+
+impl FromStr for Automaton {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut automaton = Automaton {
+            states: vec![],
+            offsets: DIRECTIONS.to_vec(),
+            transparent: None,
+            wrap: false,
+            rules: vec![],
+        };
+
+        for (idx, line) in s.lines().enumerate() {
+            let line = line.split("//").next().unwrap_or_default().trim();
+            let err = |e: String| format!("line {}: {}", idx + 1, e);
+
+            match line.split_once(' ').unwrap_or((line, "")) {
+                ("", _) => {}
+                ("states", states) => automaton.states = states.trim().bytes().collect(),
+                ("offsets", offsets) => {
+                    automaton.offsets = offsets
+                        .split_whitespace()
+                        .map(|offset| {
+                            let (x, y) = offset.split_once(',')?;
+                            Some((x.parse().ok()?, y.parse().ok()?))
+                        })
+                        .collect::<Option<_>>()
+                        .ok_or_else(|| err("can't parse offsets".to_string()))?;
+                }
+                ("line-of-sight", transparent) => {
+                    let transparent = transparent
+                        .split_whitespace()
+                        .map(|s| single_state(s, &automaton.states))
+                        .collect::<Result<_, _>>()
+                        .map_err(err)?;
+                    automaton.transparent = Some(transparent);
                 }
+                ("wrap", _) => automaton.wrap = true,
+                _ => automaton
+                    .rules
+                    .push(parse_rule(line, &automaton.states).map_err(err)?),
             }
-            None
-        })
-        .collect()
+        }
+
+        if automaton.states.is_empty() {
+            return Err("no states given".to_string());
+        }
+        let transparent = automaton.transparent.as_deref().unwrap_or_default();
+        if let Some(rule) = automaton
+            .rules
+            .iter()
+            .find(|rule| transparent.contains(&rule.from) || transparent.contains(&rule.to))
+        {
+            let state = if transparent.contains(&rule.from) {
+                rule.from
+            } else {
+                rule.to
+            };
+            return Err(format!(
+                "line-of-sight looks through '{}', so rules can't change cells to or from it",
+                state as char
+            ));
+        }
+        Ok(automaton)
+    }
+}
+
+impl Automaton {
+    fn offset_cell(
+        &self,
+        data: &Array2<u8>,
+        (y, x): (usize, usize),
+        (x_offset, y_offset): (isize, isize),
+    ) -> Option<(usize, usize)> {
+        let (rows, columns) = data.dim();
+        if self.wrap {
+            let new_x = (x as isize + x_offset).rem_euclid(columns as isize);
+            let new_y = (y as isize + y_offset).rem_euclid(rows as isize);
+            return Some((new_y as usize, new_x as usize));
+        }
+
+        let new_x = x.checked_add_signed(x_offset)?;
+        let new_y = y.checked_add_signed(y_offset)?;
+        data.get((new_y, new_x)).map(|_| (new_y, new_x))
+    }
+
+    fn neighbours(&self, data: &Array2<u8>, cell: (usize, usize)) -> Vec<(usize, usize)> {
+        // On a torus a ray is back where it started after at most
+        // rows * columns steps, even if it's diagonal and the sides differ.
+        let max_distance = (data.nrows() * data.ncols()) as isize;
+
+        self.offsets
+            .iter()
+            .filter_map(|&(xd, yd)| match &self.transparent {
+                None => self.offset_cell(data, cell, (xd, yd)),
+                Some(transparent) => (1..=max_distance)
+                    .map_while(|distance| {
+                        self.offset_cell(data, cell, (xd * distance, yd * distance))
+                    })
+                    .take_while(|&coord| coord != cell)
+                    .find(|&coord| !transparent.contains(&data[coord])),
+            })
+            .collect()
+    }
+
+    /// `neighbours` are indices into `cells`, the old layout in row order.
+    fn next_state(&self, old: u8, cells: &[u8], neighbours: &[u32]) -> u8 {
+        self.rules
+            .iter()
+            .filter(|rule| rule.from == old)
+            .find(|rule| {
+                // Comparing with n only needs the count up to n + 1, so stop
+                // looking once it gets there.
+                let count = neighbours
+                    .iter()
+                    .filter(|&&i| cells[i as usize] == rule.counted)
+                    .take(rule.n.saturating_add(1))
+                    .count();
+                rule.applies(count)
+            })
+            .map_or(old, |rule| rule.to)
+    }
+
+    /// Applies the rules until the layout stops changing, starts repeating
+    /// itself or `max_steps` generations have passed.
+    ///
+    /// Repeats are found by hashing every layout, so in principle a hash
+    /// collision could report a cycle which isn't there.
+    fn simulate(
+        &self,
+        data: &Array2<u8>,
+        max_steps: Option<usize>,
+    ) -> Result<(Array2<u8>, Convergence), String> {
+        self.simulate_with(data, max_steps, |_, _, _, _| {})
+    }
+
+    /// Like `simulate`, calling `on_generation` with the generation number,
+    /// previous and new layouts and the number of cells which flipped after
+    /// every generation that changed something.
+    fn simulate_with(
+        &self,
+        data: &Array2<u8>,
+        max_steps: Option<usize>,
+        mut on_generation: impl FnMut(usize, &Array2<u8>, &Array2<u8>, usize),
+    ) -> Result<(Array2<u8>, Convergence), String> {
+        if let Some(&bad) = data.iter().find(|s| !self.states.contains(s)) {
+            return Err(format!("layout has unknown state '{}'", bad as char));
+        }
+
+        let mut old_data: Array2<u8> = data.as_standard_layout().into_owned();
+        let mut new_data: Array2<u8> = old_data.clone();
+
+        // Cells in a state without rules never change, so only precompute
+        // neighbours for the rest. They're kept as row-order indices, which
+        // take a quarter of the memory of coordinates.
+        let columns = data.ncols();
+        if data.len() > u32::MAX as usize {
+            return Err("layout has too many cells".to_string());
+        }
+        let neighbours: Array2<Vec<u32>> = Array2::from_shape_fn(data.raw_dim(), |cell| {
+            if self.rules.iter().any(|rule| rule.from == data[cell]) {
+                self.neighbours(data, cell)
+                    .into_iter()
+                    .map(|(y, x)| (y * columns + x) as u32)
+                    .collect()
+            } else {
+                Vec::with_capacity(0)
+            }
+        });
+
+        let mut seen: AHashMap<u64, usize> = AHashMap::default();
+        seen.insert(layout_hash(data), 0);
+
+        let mut generation = 0;
+        let convergence = loop {
+            std::mem::swap(&mut old_data, &mut new_data);
+
+            let cells = old_data.as_slice().unwrap();
+            let flipped = Zip::from(&mut new_data)
+                .and(&old_data)
+                .and(&neighbours)
+                .fold(0, |acc, new, &old, neighbours| {
+                    *new = self.next_state(old, cells, neighbours);
+                    acc + usize::from(old != *new)
+                });
+            if flipped == 0 {
+                break Convergence::Stable(generation);
+            }
+            // Only give up once the next generation shows the layout is
+            // still changing, and return the last one within the limit.
+            if max_steps.is_some_and(|m| generation >= m) {
+                std::mem::swap(&mut old_data, &mut new_data);
+                break Convergence::StepLimit(generation);
+            }
+            generation += 1;
+            on_generation(generation, &old_data, &new_data, flipped);
+
+            if let Some(start) = seen.insert(layout_hash(&new_data), generation) {
+                break Convergence::Cycle {
+                    start,
+                    length: generation - start,
+                };
+            }
+        };
+
+        Ok((new_data, convergence))
+    }
+}
+
+fn layout_hash(data: &Array2<u8>) -> u64 {
+    // Hashing the bytes in one go is far quicker than ndarray's hash, which
+    // goes element by element.
+    let mut hasher = AHasher::default();
+    match data.as_slice() {
+        Some(cells) => cells.hash(&mut hasher),
+        None => data.hash(&mut hasher),
+    }
+    hasher.finish()
+}
+
+const GIF_PALETTE: [[u8; 3]; 16] = [
+    [40, 40, 40],
+    [80, 170, 80],
+    [210, 60, 60],
+    [70, 110, 220],
+    [230, 200, 60],
+    [170, 80, 200],
+    [60, 200, 200],
+    [240, 240, 240],
+    [120, 120, 120],
+    [40, 100, 40],
+    [130, 30, 30],
+    [30, 50, 130],
+    [150, 120, 30],
+    [90, 40, 110],
+    [30, 110, 110],
+    [190, 190, 190],
+];
+/// Side length in pixels of each cell.
+const GIF_SCALE: usize = 4;
+/// Time between frames, in hundredths of a second.
+const GIF_DELAY: u16 = 20;
+
+fn gif_header(buf: &mut Vec<u8>, width: u16, height: u16) {
+    buf.extend(b"GIF89a");
+    buf.extend(width.to_le_bytes());
+    buf.extend(height.to_le_bytes());
+    // Global colour table of 16 entries, no background or aspect ratio.
+    buf.extend([0xF3, 0, 0]);
+    buf.extend(GIF_PALETTE.iter().flatten());
+    // Loop forever.
+    buf.extend([0x21, 0xFF, 0x0B]);
+    buf.extend(b"NETSCAPE2.0");
+    buf.extend([0x03, 0x01, 0x00, 0x00, 0x00]);
+}
+
+/// Writes `pixels` (indices into `GIF_PALETTE`) as one frame. The image data
+/// is LZW-encoded without any actual compression: every pixel is a 5-bit
+/// literal, with a clear code every 14 pixels so the decoder's code size
+/// never grows.
+fn gif_frame(buf: &mut Vec<u8>, pixels: &[u8], width: u16, height: u16) {
+    buf.extend([0x21, 0xF9, 0x04, 0x00]);
+    buf.extend(GIF_DELAY.to_le_bytes());
+    buf.extend([0x00, 0x00]);
+
+    buf.extend([0x2C, 0, 0, 0, 0]);
+    buf.extend(width.to_le_bytes());
+    buf.extend(height.to_le_bytes());
+    buf.extend([0x00, 0x04]);
+
+    const CLEAR: u32 = 16;
+    const END: u32 = 17;
+    let mut data: Vec<u8> = vec![];
+    let mut bits: u32 = 0;
+    let mut bit_count = 0;
+    let mut emit = |code: u32| {
+        bits |= code << bit_count;
+        bit_count += 5;
+        while bit_count >= 8 {
+            data.push(bits as u8);
+            bits >>= 8;
+            bit_count -= 8;
+        }
+    };
+    for chunk in pixels.chunks(14) {
+        emit(CLEAR);
+        chunk.iter().for_each(|&p| emit(u32::from(p)));
+    }
+    emit(END);
+    if bit_count > 0 {
+        data.push(bits as u8);
+    }
+
+    for block in data.chunks(255) {
+        buf.push(block.len() as u8);
+        buf.extend(block);
+    }
+    buf.push(0);
+}
+
+/// Collects each generation of a simulation for `--evolution`.
+enum Recorder {
+    Text(String),
+    Gif { buf: Vec<u8>, states: Vec<u8> },
+    Delta(Vec<u8>),
+}
+
+impl Recorder {
+    fn new(format: EvolutionFormat, states: &[u8], data: &Array2<u8>) -> Self {
+        let mut recorder = match format {
+            EvolutionFormat::Text => Recorder::Text(String::new()),
+            EvolutionFormat::Gif => {
+                let (rows, columns) = data.dim();
+                let width = u16::try_from(columns * GIF_SCALE).expect("layout too wide for GIF");
+                let height = u16::try_from(rows * GIF_SCALE).expect("layout too tall for GIF");
+                let mut buf = vec![];
+                gif_header(&mut buf, width, height);
+                Recorder::Gif {
+                    buf,
+                    states: states.to_vec(),
+                }
+            }
+            EvolutionFormat::Delta => {
+                let mut buf = b"SDL1".to_vec();
+                buf.extend((data.nrows() as u32).to_le_bytes());
+                buf.extend((data.ncols() as u32).to_le_bytes());
+                Recorder::Delta(buf)
+            }
+        };
+
+        recorder.frame(0, data, 0);
+        recorder
+    }
+
+    /// Writes the whole layout, rather than just what changed.
+    fn frame(&mut self, generation: usize, data: &Array2<u8>, flipped: usize) {
+        match self {
+            Recorder::Text(out) => {
+                out.push_str(&format!(
+                    "generation {} ({} flipped)\n",
+                    generation, flipped
+                ));
+                for row in data.rows() {
+                    out.extend(row.iter().map(|&s| s as char));
+                    out.push('\n');
+                }
+                out.push('\n');
+            }
+            Recorder::Gif { buf, states } => {
+                let (rows, columns) = data.dim();
+                let mut pixels = Vec::with_capacity(rows * columns * GIF_SCALE * GIF_SCALE);
+                for row in data.rows() {
+                    let line: Vec<u8> = row
+                        .iter()
+                        .flat_map(|s| {
+                            let colour = states.iter().position(|x| x == s).unwrap_or(0) % 16;
+                            [colour as u8; GIF_SCALE]
+                        })
+                        .collect();
+                    (0..GIF_SCALE).for_each(|_| pixels.extend(&line));
+                }
+                let (width, height) = ((columns * GIF_SCALE) as u16, (rows * GIF_SCALE) as u16);
+                gif_frame(buf, &pixels, width, height);
+            }
+            Recorder::Delta(buf) => buf.extend(data.iter()),
+        }
+    }
+
+    fn record(&mut self, generation: usize, old: &Array2<u8>, new: &Array2<u8>, flipped: usize) {
+        match self {
+            Recorder::Delta(buf) => {
+                buf.extend((flipped as u32).to_le_bytes());
+                for (idx, (o, n)) in old.iter().zip(new.iter()).enumerate() {
+                    if o != n {
+                        buf.extend((idx as u32).to_le_bytes());
+                        buf.push(*n);
+                    }
+                }
+            }
+            _ => self.frame(generation, new, flipped),
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        match self {
+            Recorder::Text(out) => out.into_bytes(),
+            Recorder::Gif { mut buf, .. } => {
+                buf.push(0x3B);
+                buf
+            }
+            Recorder::Delta(mut buf) => {
+                buf.extend(0u32.to_le_bytes());
+                buf
+            }
+        }
+    }
 }
 
-type VisibleSeatsType = fn(&Array2<u8>, usize, usize) -> Vec<(usize, usize)>;
+/// Rows per rayon task when stepping a `Seating`.
+const BAND_ROWS: usize = 8;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Visibility {
+    Adjacent,
+    LineOfSight,
+}
 
-fn simulate(
-    data: &Array2<u8>,
-    visible_seats: VisibleSeatsType,
-    occupancy_tolerance: usize,
-) -> usize {
-    let mut old_data: Array2<u8> = data.clone();
-    let mut new_data: Array2<u8> = data.clone();
+/// Which seats each seat can see, in compressed sparse row form: seat `s`
+/// (numbering seats in reading order) sees the cells at bit positions
+/// `targets[offsets[s]..offsets[s + 1]]`.
+struct SightLines {
+    offsets: Vec<u32>,
+    targets: Vec<u32>,
+    /// Number of the first seat in each row, and the total number of seats.
+    row_seats: Vec<usize>,
+}
 
-    // Precompute visible seats
-    let vseats: Array2<Vec<(usize, usize)>> = Array2::from_shape_fn(data.raw_dim(), |(y, x)| {
-        if data[(y, x)] != FLOOR {
-            visible_seats(data, x, y)
+/// Adds a one-bit value to every lane of a bit-sliced counter.
+fn add_bit(count: &mut [u64; 4], mut carry: u64) {
+    for slice in count.iter_mut() {
+        let next = *slice & carry;
+        *slice ^= carry;
+        carry = next;
+    }
+}
+
+/// Lanes of a bit-sliced counter which hold at least `n`.
+fn at_least(count: &[u64; 4], n: usize) -> u64 {
+    if n >= 16 {
+        return 0;
+    }
+    let mut greater = 0;
+    let mut equal = !0;
+    for bit in (0..4).rev() {
+        if n & (1 << bit) == 0 {
+            greater |= equal & count[bit];
+            equal &= !count[bit];
         } else {
-            Vec::with_capacity(0)
-        }
-    });
-
-    let mut any_changed = true;
-    while any_changed {
-        std::mem::swap(&mut old_data, &mut new_data);
-
-        Zip::from(&mut new_data)
-            .and(&old_data)
-            .and(&vseats)
-            .for_each(|new, &old, vseats| {
-                if old != FLOOR {
-                    *new = match old {
-                        EMPTY => {
-                            let no_visible_occupied =
-                                vseats.iter().all(|&coord| old_data[coord] != OCCUPIED);
-                            if no_visible_occupied {
-                                OCCUPIED
-                            } else {
-                                EMPTY
+            equal &= count[bit];
+        }
+    }
+    greater | equal
+}
+
+/// The two seating puzzles on bitboards, for layouts too big for
+/// `Automaton`. Each row is padded to a whole number of 64-bit words.
+struct Seating {
+    rows: usize,
+    columns: usize,
+    words: usize,
+    seats: Vec<u64>,
+    occupied: Vec<u64>,
+}
+
+impl Seating {
+    fn new(data: &Array2<u8>) -> Self {
+        let (rows, columns) = data.dim();
+        let words = columns.div_ceil(64);
+        let mut seats = vec![0; rows * words];
+        let mut occupied = vec![0; rows * words];
+        for ((y, x), &cell) in data.indexed_iter() {
+            let (word, bit) = (y * words + x / 64, 1 << (x % 64));
+            if cell != b'.' {
+                seats[word] |= bit;
+            }
+            if cell == OCCUPIED {
+                occupied[word] |= bit;
+            }
+        }
+
+        Seating {
+            rows,
+            columns,
+            words,
+            seats,
+            occupied,
+        }
+    }
+
+    fn bit(&self, (y, x): (usize, usize)) -> usize {
+        y * self.words * 64 + x
+    }
+
+    fn is_seat(&self, cell: (usize, usize)) -> bool {
+        let bit = self.bit(cell);
+        self.seats[bit / 64] >> (bit % 64) & 1 == 1
+    }
+
+    /// Calls `f` with every pair of seats which can see each other, walking
+    /// each row, column and diagonal once.
+    fn for_each_sight_pair(&self, mut f: impl FnMut((usize, usize), (usize, usize))) {
+        for (dy, dx) in [(0, 1), (1, 0), (1, 1), (1, -1)] {
+            for y in 0..self.rows {
+                for x in 0..self.columns {
+                    let starts_line = match (y.checked_sub(dy), x.checked_add_signed(-dx)) {
+                        (Some(_), Some(px)) => px >= self.columns,
+                        _ => true,
+                    };
+                    if !starts_line {
+                        continue;
+                    }
+
+                    let mut last = None;
+                    let mut cell = (y, x);
+                    loop {
+                        if self.is_seat(cell) {
+                            if let Some(last) = last {
+                                f(last, cell);
                             }
+                            last = Some(cell);
                         }
-                        OCCUPIED => {
-                            let many_visible_occupied = vseats
-                                .iter()
-                                .filter(|&&coord| old_data[coord] == OCCUPIED)
-                                .count()
-                                >= occupancy_tolerance;
-                            if many_visible_occupied {
-                                EMPTY
-                            } else {
-                                OCCUPIED
+                        match (cell.0 + dy, cell.1.checked_add_signed(dx)) {
+                            (ny, Some(nx)) if ny < self.rows && nx < self.columns => {
+                                cell = (ny, nx)
                             }
+                            _ => break,
                         }
-                        _ => old,
                     }
                 }
-            });
+            }
+        }
+    }
+
+    /// Fails if the layout is too big to index with u32s.
+    fn sight_lines(&self) -> Result<SightLines, String> {
+        let bits = (self.rows * self.words).checked_mul(64);
+        if bits.is_none_or(|bits| bits > u32::MAX as usize) {
+            return Err("layout too large for line-of-sight".to_string());
+        }
+
+        let mut row_seats = Vec::with_capacity(self.rows + 1);
+        let mut seat_numbers = vec![0u32; self.rows * self.columns];
+        let mut seats = 0;
+        for y in 0..self.rows {
+            row_seats.push(seats);
+            for x in 0..self.columns {
+                if self.is_seat((y, x)) {
+                    seat_numbers[y * self.columns + x] = seats as u32;
+                    seats += 1;
+                }
+            }
+        }
+        row_seats.push(seats);
+        let number = |(y, x): (usize, usize)| seat_numbers[y * self.columns + x] as usize;
+
+        let mut offsets = vec![0u32; seats + 1];
+        self.for_each_sight_pair(|a, b| {
+            offsets[number(a) + 1] += 1;
+            offsets[number(b) + 1] += 1;
+        });
+        for idx in 1..=seats {
+            offsets[idx] = offsets[idx]
+                .checked_add(offsets[idx - 1])
+                .ok_or("too many sight lines for line-of-sight")?;
+        }
+
+        let mut next = offsets.clone();
+        let mut targets = vec![0u32; offsets[seats] as usize];
+        self.for_each_sight_pair(|a, b| {
+            for (from, to) in [(a, b), (b, a)] {
+                let slot = &mut next[number(from)];
+                targets[*slot as usize] = self.bit(to) as u32;
+                *slot += 1;
+            }
+        });
+
+        Ok(SightLines {
+            offsets,
+            targets,
+            row_seats,
+        })
+    }
+
+    /// One generation of the adjacency rule for the rows of `new`, starting
+    /// at `first_row`. Neighbours are counted for 64 cells at a time by
+    /// adding shifted copies of the surrounding words into a bit-sliced
+    /// counter. Returns whether anything changed.
+    fn step_adjacent(
+        &self,
+        old: &[u64],
+        new: &mut [u64],
+        first_row: usize,
+        tolerance: usize,
+    ) -> bool {
+        let words = self.words;
+        let row = |y: Option<usize>| {
+            y.filter(|&y| y < self.rows)
+                .map(|y| &old[y * words..(y + 1) * words])
+        };
+
+        let mut changed = false;
+        for (idx, new_row) in new.chunks_mut(words).enumerate() {
+            let y = first_row + idx;
+            let neighbour_rows = [row(y.checked_sub(1)), row(Some(y)), row(Some(y + 1))];
+
+            for (w, new_word) in new_row.iter_mut().enumerate() {
+                let mut count = [0; 4];
+                for (ry, cells) in neighbour_rows.iter().enumerate() {
+                    let Some(cells) = cells else { continue };
+                    let here = cells[w];
+                    let before = if w > 0 { cells[w - 1] } else { 0 };
+                    let after = cells.get(w + 1).copied().unwrap_or(0);
+
+                    add_bit(&mut count, here << 1 | before >> 63);
+                    add_bit(&mut count, here >> 1 | after << 63);
+                    if ry != 1 {
+                        add_bit(&mut count, here);
+                    }
+                }
+
+                let idx = y * words + w;
+                let occupied = old[idx];
+                let none = !(count[0] | count[1] | count[2] | count[3]);
+                let stays = !at_least(&count, tolerance);
+                *new_word = self.seats[idx] & ((!occupied & none) | (occupied & stays));
+                changed |= *new_word != occupied;
+            }
+        }
+        changed
+    }
+
+    /// One generation of the line-of-sight rule for the rows of `new`,
+    /// starting at `first_row`.
+    fn step_line_of_sight(
+        &self,
+        lines: &SightLines,
+        old: &[u64],
+        new: &mut [u64],
+        first_row: usize,
+        tolerance: usize,
+    ) -> bool {
+        let mut changed = false;
+        for (idx, new_row) in new.chunks_mut(self.words).enumerate() {
+            let y = first_row + idx;
+            let mut seat = lines.row_seats[y];
+
+            for (w, new_word) in new_row.iter_mut().enumerate() {
+                let idx = y * self.words + w;
+                let occupied = old[idx];
+                let mut seats = self.seats[idx];
+                let mut word = 0;
+                while seats != 0 {
+                    let bit = seats & seats.wrapping_neg();
+                    seats ^= bit;
+
+                    let visible = &lines.targets
+                        [lines.offsets[seat] as usize..lines.offsets[seat + 1] as usize];
+                    let count = visible
+                        .iter()
+                        .filter(|&&t| old[t as usize / 64] >> (t % 64) & 1 == 1)
+                        .count();
+                    let was_occupied = occupied & bit != 0;
+                    if (!was_occupied && count == 0) || (was_occupied && count < tolerance) {
+                        word |= bit;
+                    }
+                    seat += 1;
+                }
+                *new_word = word;
+                changed |= word != occupied;
+            }
+        }
+        changed
+    }
 
-        any_changed = old_data != new_data;
+    /// For each band of rows, the first and last bands holding a cell which
+    /// its seats can see.
+    fn band_reach(&self, lines: Option<&SightLines>) -> Vec<(usize, usize)> {
+        let bands = self.rows.div_ceil(BAND_ROWS);
+        (0..bands)
+            .map(|band| {
+                let mut reach = (band.saturating_sub(1), (band + 1).min(bands - 1));
+                if let Some(lines) = lines {
+                    let first_seat = lines.row_seats[band * BAND_ROWS];
+                    let last_seat = lines.row_seats[((band + 1) * BAND_ROWS).min(self.rows)];
+                    let targets = &lines.targets
+                        [lines.offsets[first_seat] as usize..lines.offsets[last_seat] as usize];
+                    for &target in targets {
+                        let target_band = target as usize / (self.words * 64) / BAND_ROWS;
+                        reach = (reach.0.min(target_band), reach.1.max(target_band));
+                    }
+                }
+                reach
+            })
+            .collect()
     }
 
-    new_data.iter().filter(|&s| s == &OCCUPIED).count()
+    /// Runs the seating rules until nobody moves or the layout starts
+    /// repeating itself, returning the number of occupied seats.
+    ///
+    /// Each generation is split into bands of rows which are stepped in
+    /// parallel. A band is only recomputed if something it can see changed
+    /// in the last generation, so once most of the layout has settled only
+    /// the few bands still moving cost anything.
+    fn settle(
+        &self,
+        visibility: Visibility,
+        tolerance: usize,
+    ) -> Result<(usize, Convergence), String> {
+        let lines = match visibility {
+            Visibility::LineOfSight => Some(self.sight_lines()?),
+            Visibility::Adjacent => None,
+        };
+        let reach = self.band_reach(lines.as_ref());
+        let band_words = self.words * BAND_ROWS;
+
+        let mut old = self.occupied.clone();
+        let mut new = self.occupied.clone();
+        let mut changed = vec![true; reach.len()];
+
+        let mut seen: AHashMap<u64, usize> = AHashMap::default();
+        seen.insert(bitboard_hash(&new), 0);
+
+        let mut generation = 0;
+        let convergence = loop {
+            std::mem::swap(&mut old, &mut new);
+
+            changed = new
+                .par_chunks_mut(band_words)
+                .enumerate()
+                .map(|(band, chunk)| {
+                    let (first, last) = reach[band];
+                    if !changed[first..=last].contains(&true) {
+                        let start = band * band_words;
+                        chunk.copy_from_slice(&old[start..start + chunk.len()]);
+                        return false;
+                    }
+
+                    let first_row = band * BAND_ROWS;
+                    match &lines {
+                        None => self.step_adjacent(&old, chunk, first_row, tolerance),
+                        Some(lines) => {
+                            self.step_line_of_sight(lines, &old, chunk, first_row, tolerance)
+                        }
+                    }
+                })
+                .collect();
+            if !changed.contains(&true) {
+                break Convergence::Stable(generation);
+            }
+            generation += 1;
+
+            if let Some(start) = seen.insert(bitboard_hash(&new), generation) {
+                break Convergence::Cycle {
+                    start,
+                    length: generation - start,
+                };
+            }
+        };
+
+        let occupied = new.iter().map(|w| w.count_ones() as usize).sum();
+        Ok((occupied, convergence))
+    }
 }
 
-fn calculate_p1(data: &Array2<u8>) -> usize {
-    simulate(data, visible_occupied_seats_p1, 4)
+fn bitboard_hash(words: &[u64]) -> u64 {
+    let mut hasher = AHasher::default();
+    words.hash(&mut hasher);
+    hasher.finish()
 }
 
-fn calculate_p2(data: &Array2<u8>) -> usize {
-    simulate(data, visible_occupied_seats_p2, 5)
+/// A square layout of empty seats with roughly one cell in eight being
+/// floor, like the puzzle inputs.
+fn generate(size: usize, seed: u64) -> String {
+    let mut rng = Rng::new(seed);
+    let mut out = String::with_capacity(size * (size + 1));
+    for _ in 0..size {
+        out.extend((0..size).map(|_| {
+            if rng.next_u64() < u64::MAX / 8 {
+                '.'
+            } else {
+                'L'
+            }
+        }));
+        out.push('\n');
+    }
+    out
 }
 
+fn count_state(data: &Array2<u8>, state: u8) -> usize {
+    data.iter().filter(|&&s| s == state).count()
+}
+
+fn calculate_p1(data: &Array2<u8>) -> Result<usize, String> {
+    let automaton: Automaton = SEATING_P1.parse().unwrap();
+    let (result, convergence) = automaton.simulate(data, None)?;
+    settled_count(count_state(&result, OCCUPIED), convergence)
+}
+
+fn calculate_p2(data: &Array2<u8>) -> Result<usize, String> {
+    let automaton: Automaton = SEATING_P2.parse().unwrap();
+    let (result, convergence) = automaton.simulate(data, None)?;
+    settled_count(count_state(&result, OCCUPIED), convergence)
+}
+
+fn calculate_p1_bitboard(data: &Array2<u8>) -> Result<usize, String> {
+    let (occupied, convergence) = Seating::new(data).settle(Visibility::Adjacent, 4)?;
+    settled_count(occupied, convergence)
+}
+
+fn calculate_p2_bitboard(data: &Array2<u8>) -> Result<usize, String> {
+    let (occupied, convergence) = Seating::new(data).settle(Visibility::LineOfSight, 5)?;
+    settled_count(occupied, convergence)
+}
+
+type Solver = fn(&Array2<u8>) -> Result<usize, String>;
+
 fn main() {
     let args = Cli::parse();
-    let raw_inp = fs::read_to_string(args.input).expect("can't open input file");
+
+    if let Some(size) = args.generate {
+        print!("{}", generate(size, args.seed));
+        return;
+    }
+
+    let raw_inp = fs::read_to_string(args.input.unwrap()).expect("can't open input file");
     let data = parse(&raw_inp);
-    let results: Vec<usize> = vec![calculate_p1, calculate_p2]
-        .par_iter()
-        .map(|f| f(&data))
-        .collect();
-    let p1 = results[0];
-    let p2 = results[1];
-    println!("{}\n{}", p1, p2);
+
+    if args.rules.is_none() && args.evolution.is_none() {
+        let solvers: [Solver; 2] = if args.bitboard {
+            [calculate_p1_bitboard, calculate_p2_bitboard]
+        } else {
+            [calculate_p1, calculate_p2]
+        };
+        let results: Vec<Result<usize, String>> = solvers.par_iter().map(|f| f(&data)).collect();
+        for (part, result) in results.into_iter().enumerate() {
+            match result {
+                Ok(occupied) => println!("{}", occupied),
+                Err(e) => println!("p{}: {}", part + 1, e),
+            }
+        }
+        return;
+    }
+
+    let raw_rules = match args.rules {
+        Some(rules) => fs::read_to_string(rules).expect("can't open rule file"),
+        None if args.part == 2 => SEATING_P2.to_string(),
+        None => SEATING_P1.to_string(),
+    };
+    let automaton: Automaton = raw_rules
+        .parse()
+        .unwrap_or_else(|e| panic!("invalid rule file: {}", e));
+
+    let (result, convergence) = match args.evolution {
+        None => automaton.simulate(&data, args.max_steps),
+        Some(format) => {
+            let mut recorder = Recorder::new(format, &automaton.states, &data);
+            let result =
+                automaton.simulate_with(&data, args.max_steps, |generation, old, new, flipped| {
+                    println!("generation {}: {} flipped", generation, flipped);
+                    recorder.record(generation, old, new, flipped);
+                });
+            fs::write(args.output.unwrap(), recorder.finish()).expect("can't write output");
+            result
+        }
+    }
+    .unwrap_or_else(|e| panic!("{}", e));
+
+    println!("{}", convergence);
+    for &state in &automaton.states {
+        println!("{}: {}", state as char, count_state(&result, state));
+    }
 }
 
 #[cfg(test)]
@@ -163,21 +1083,282 @@ mod tests {
 
     #[test]
     fn test_p1_example() {
-        assert_eq!(calculate_p1(&parse(TEST_DATA)), 37);
+        assert_eq!(calculate_p1(&parse(TEST_DATA)), Ok(37));
     }
 
     #[test]
     fn test_p2_example() {
-        assert_eq!(calculate_p2(&parse(TEST_DATA)), 26);
+        assert_eq!(calculate_p2(&parse(TEST_DATA)), Ok(26));
+    }
+
+    #[test]
+    fn test_parse_rule_file() {
+        let automaton: Automaton = "// von Neumann neighbourhood on a torus
+            states .o
+            offsets 0,-1 -1,0 1,0 0,1
+            wrap
+            . -> o if o >= 2  // birth
+            o -> . if o < 1"
+            .parse()
+            .unwrap();
+
+        assert_eq!(automaton.states, b".o");
+        assert_eq!(automaton.offsets, [(0, -1), (-1, 0), (1, 0), (0, 1)]);
+        assert!(automaton.wrap);
+        assert_eq!(automaton.transparent, None);
+        assert_eq!(
+            automaton.rules[1],
+            Rule {
+                from: b'o',
+                to: b'.',
+                counted: b'o',
+                cmp: Comparison::Lt,
+                n: 1
+            }
+        );
+
+        assert_eq!(
+            "states .L#\nL -> X if # == 0".parse::<Automaton>().err(),
+            Some("line 2: unknown state 'X'".to_string())
+        );
+        assert_eq!(
+            "states .L#\nline-of-sight . L\n# -> L if # >= 5"
+                .parse::<Automaton>()
+                .err(),
+            Some(
+                "line-of-sight looks through 'L', so rules can't change cells to or from it"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_line_of_sight_torus() {
+        let automaton: Automaton = "states .L#
+            offsets 1,1
+            line-of-sight .
+            wrap"
+            .parse()
+            .unwrap();
+
+        // Going down and right from the top left passes three floor cells
+        // before it reaches the occupied seat.
+        let layout = parse("L#.\n...");
+        assert_eq!(automaton.neighbours(&layout, (0, 0)), [(0, 1)]);
+        assert_eq!(automaton.neighbours(&parse("L..\n..."), (0, 0)), []);
+    }
+
+    #[test]
+    fn test_custom_automaton() {
+        let mut automaton: Automaton = "states .o
+            offsets -1,0 1,0
+            wrap
+            . -> o if o == 2"
+            .parse()
+            .unwrap();
+        let layout = parse(".o.o");
+
+        let (result, convergence) = automaton.simulate(&layout, None).unwrap();
+        assert_eq!(result.as_slice().unwrap(), b"oooo");
+        assert_eq!(convergence, Convergence::Stable(1));
+
+        automaton.wrap = false;
+        let (result, _) = automaton.simulate(&layout, None).unwrap();
+        assert_eq!(result.as_slice().unwrap(), b".ooo");
+
+        assert_eq!(
+            automaton.simulate(&parse(".x"), None),
+            Err("layout has unknown state 'x'".to_string())
+        );
+    }
+
+    fn record(
+        format: EvolutionFormat,
+        automaton: &Automaton,
+        layout: &Array2<u8>,
+    ) -> (Vec<u8>, Vec<usize>) {
+        let mut recorder = Recorder::new(format, &automaton.states, layout);
+        let mut flips = vec![];
+        automaton
+            .simulate_with(layout, None, |generation, old, new, flipped| {
+                flips.push(flipped);
+                recorder.record(generation, old, new, flipped);
+            })
+            .unwrap();
+        (recorder.finish(), flips)
+    }
+
+    const SPREAD: &str = "states .o
+        offsets -1,0 1,0
+        . -> o if o >= 1";
+
+    #[test]
+    fn test_evolution_text() {
+        let automaton: Automaton = SPREAD.parse().unwrap();
+        let (out, flips) = record(EvolutionFormat::Text, &automaton, &parse("..o.\n...."));
+        assert_eq!(flips, [2, 1]);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "generation 0 (0 flipped)
+..o.
+....
+
+generation 1 (2 flipped)
+.ooo
+....
+
+generation 2 (1 flipped)
+oooo
+....
+
+"
+        );
+    }
+
+    #[test]
+    fn test_evolution_delta() {
+        let automaton: Automaton = SPREAD.parse().unwrap();
+        let (out, flips) = record(EvolutionFormat::Delta, &automaton, &parse("o..\n..."));
+        assert_eq!(flips, [1, 1]);
+
+        let mut expected = b"SDL1\x02\0\0\0\x03\0\0\0o.....".to_vec();
+        expected.extend([1, 0, 0, 0, 1, 0, 0, 0, b'o']);
+        expected.extend([1, 0, 0, 0, 2, 0, 0, 0, b'o']);
+        expected.extend([0, 0, 0, 0]);
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_evolution_gif() {
+        let automaton: Automaton = SPREAD.parse().unwrap();
+        let (out, _) = record(EvolutionFormat::Gif, &automaton, &parse("o..\n..."));
+        assert_eq!(&out[..6], b"GIF89a");
+        assert_eq!(&out[6..10], [12, 0, 8, 0]);
+        assert_eq!(out.last(), Some(&0x3B));
+
+        // 3 frames of 12x8 pixels, each a run of 5-bit codes with a clear
+        // code every 14 pixels and an end code.
+        let codes: usize = 96 + 96 / 14 + 1 + 1;
+        let frame_len = 8 + 10 + 1 + (codes * 5).div_ceil(8) + 1 + 1;
+        assert_eq!(out.len(), 6 + 7 + 48 + 19 + 3 * frame_len + 1);
+    }
+
+    #[test]
+    fn test_oscillation() {
+        let automaton: Automaton = "states .oX
+            X -> o if o >= 0
+            o -> . if o >= 0
+            . -> o if o >= 0"
+            .parse()
+            .unwrap();
+
+        let (_, convergence) = automaton.simulate(&parse("o.\n.o"), None).unwrap();
+        assert_eq!(
+            convergence,
+            Convergence::Cycle {
+                start: 0,
+                length: 2
+            }
+        );
+
+        let (result, convergence) = automaton.simulate(&parse("X."), None).unwrap();
+        assert_eq!(
+            convergence,
+            Convergence::Cycle {
+                start: 1,
+                length: 2
+            }
+        );
+        assert_eq!(result.as_slice().unwrap(), b"oo");
+
+        let (_, convergence) = automaton.simulate(&parse("X."), Some(2)).unwrap();
+        assert_eq!(convergence, Convergence::StepLimit(2));
+
+        let (result, convergence) = automaton.simulate(&parse("X."), Some(0)).unwrap();
+        assert_eq!(convergence, Convergence::StepLimit(0));
+        assert_eq!(result.as_slice().unwrap(), b"X.");
+
+        // Settles at exactly the limit, so it's stable rather than still
+        // changing.
+        let automaton: Automaton = SPREAD.parse().unwrap();
+        let (result, convergence) = automaton.simulate(&parse("o.."), Some(2)).unwrap();
+        assert_eq!(convergence, Convergence::Stable(2));
+        assert_eq!(result.as_slice().unwrap(), b"ooo");
+
+        let (result, convergence) = automaton.simulate(&parse("o.."), Some(1)).unwrap();
+        assert_eq!(convergence, Convergence::StepLimit(1));
+        assert_eq!(result.as_slice().unwrap(), b"oo.");
+    }
+
+    #[test]
+    fn test_bitboard_example() {
+        assert_eq!(calculate_p1_bitboard(&parse(TEST_DATA)), Ok(37));
+        assert_eq!(calculate_p2_bitboard(&parse(TEST_DATA)), Ok(26));
+    }
+
+    #[test]
+    fn test_bitboard_matches_automaton() {
+        // Wider than two words, and not a multiple of 64, to cover the
+        // carries between words and the padding at the end of each row.
+        let generated = generate(150, 7);
+        let data = parse(&generated);
+        let data = data.slice(ndarray::s![..70, ..]).to_owned();
+
+        assert_eq!(calculate_p2_bitboard(&data), calculate_p2(&data));
+        assert!(calculate_p2(&data).is_ok());
+
+        // Random layouts don't always settle under the adjacency rule, and
+        // then there's no answer.
+        let automaton: Automaton = SEATING_P1.parse().unwrap();
+        let (_, convergence) = automaton.simulate(&data, None).unwrap();
+        assert_eq!(
+            Seating::new(&data)
+                .settle(Visibility::Adjacent, 4)
+                .unwrap()
+                .1,
+            convergence
+        );
+        assert!(matches!(convergence, Convergence::Cycle { length: 2, .. }));
+        assert_eq!(
+            calculate_p1(&data),
+            Err(format!("layout never settles, {}", convergence))
+        );
+        assert_eq!(calculate_p1_bitboard(&data), calculate_p1(&data));
+    }
+
+    #[test]
+    fn test_sight_lines() {
+        let seating = Seating::new(&parse("L.L\n...\n#.L"));
+        let lines = seating.sight_lines().unwrap();
+        assert_eq!(lines.row_seats, [0, 2, 2, 4]);
+        assert_eq!(lines.offsets, [0, 3, 6, 9, 12]);
+
+        let bit = |cell| seating.bit(cell) as u32;
+        let mut seen: Vec<u32> = lines.targets[0..3].to_vec();
+        seen.sort();
+        assert_eq!(seen, [bit((0, 2)), bit((2, 0)), bit((2, 2))]);
+
+        // Too many cells to number with a u32, checked before looking at any.
+        let huge = Seating {
+            rows: 1 << 26,
+            columns: 64,
+            words: 1,
+            seats: vec![],
+            occupied: vec![],
+        };
+        assert_eq!(
+            huge.sight_lines().err(),
+            Some("layout too large for line-of-sight".to_string())
+        );
     }
 
     #[test]
     fn test_p1_real() {
-        assert_eq!(calculate_p1(&parse(REAL_DATA)), 2289);
+        assert_eq!(calculate_p1(&parse(REAL_DATA)), Ok(2289));
     }
 
     #[test]
     fn test_p2_real() {
-        assert_eq!(calculate_p2(&parse(REAL_DATA)), 2059);
+        assert_eq!(calculate_p2(&parse(REAL_DATA)), Ok(2059));
     }
 }
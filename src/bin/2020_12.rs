@@ -1,10 +1,75 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use std::fmt;
 use std::fs;
+use std::ops::{Add, Mul, Neg, Sub};
 
 #[derive(Parser)]
 struct Cli {
     #[clap(short, long)]
     input: String,
+
+    /// Which arithmetic to navigate with, which decides the turns allowed
+    #[clap(long, value_enum, default_value_t = Arithmetic::Integer)]
+    arithmetic: Arithmetic,
+
+    /// With float arithmetic, round values this close to a whole number
+    #[clap(long, default_value_t = 1e-9)]
+    tolerance: f64,
+
+    /// Write the path taken to the output file instead of the answers
+    #[clap(long, value_enum, requires = "output")]
+    export: Option<ExportFormat>,
+
+    #[clap(long)]
+    output: Option<String>,
+
+    /// Print how far the ship went instead of the answers
+    #[clap(long)]
+    stats: bool,
+
+    /// Which puzzle's rules to use with --export and --stats
+    #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=2))]
+    part: u8,
+
+    /// Latitude and longitude of the starting point, for GeoJSON
+    #[clap(
+        long,
+        default_value = "0,0",
+        value_parser = parse_origin,
+        allow_hyphen_values = true
+    )]
+    origin: (f64, f64),
+
+    /// Metres per unit of distance, for GeoJSON
+    #[clap(long, default_value_t = 1.0)]
+    scale: f64,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Arithmetic {
+    /// Turns must be multiples of 90 degrees
+    Integer,
+    /// Turns must be multiples of 45 degrees, distances are exact like 3+2√2
+    Exact,
+    /// Any whole number of degrees
+    Float,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    /// One row per instruction with the positions after it
+    Csv,
+    /// A LineString feature of the ship's path
+    Geojson,
+    /// A drawing of the ship's path, and the waypoint's in part 2
+    Svg,
+}
+
+fn parse_origin(s: &str) -> Result<(f64, f64), String> {
+    let (lat, lon) = s.split_once(',').ok_or("expected latitude,longitude")?;
+    let lat = lat.trim().parse().or(Err("can't parse latitude"))?;
+    let lon = lon.trim().parse().or(Err("can't parse longitude"))?;
+    Ok((lat, lon))
 }
 
 fn parse(raw_inp: &str) -> Vec<(u8, i64)> {
@@ -20,80 +85,478 @@ fn parse(raw_inp: &str) -> Vec<(u8, i64)> {
         .collect()
 }
 
-fn calculate_p1(data: &Vec<(u8, i64)>) -> i64 {
-    let mut x_pos: i64 = 0;
-    let mut y_pos: i64 = 0;
-    let mut dir: i64 = 90;
+/// An exact number (a + b√2) / 2^k, which is closed under turns of 45
+/// degrees.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Surd {
+    a: i128,
+    b: i128,
+    k: u32,
+}
 
-    for (command, n) in data {
-        match command {
-            b'N' => y_pos += n,
-            b'S' => y_pos -= n,
-            b'E' => x_pos += n,
-            b'W' => x_pos -= n,
-            b'L' => dir = (dir - n).rem_euclid(360),
-            b'R' => dir = (dir + n).rem_euclid(360),
-            b'F' => match dir {
-                0 => y_pos += n,
-                90 => x_pos += n,
-                180 => y_pos -= n,
-                270 => x_pos -= n,
-                _ => panic!("invalid dir"),
-            },
-            _ => panic!("invalid command"),
+impl Surd {
+    fn new(mut a: i128, mut b: i128, mut k: u32) -> Self {
+        while k > 0 && a % 2 == 0 && b % 2 == 0 {
+            (a, b, k) = (a / 2, b / 2, k - 1);
         }
+        Surd { a, b, k }
+    }
+
+    /// Multiplies by √2/2, i.e. cos 45°.
+    fn times_sqrt_half(self) -> Self {
+        Surd::new(2 * self.b, self.a, self.k + 1)
     }
 
-    x_pos.abs() + y_pos.abs()
+    fn signum(self) -> i128 {
+        let (sa, sb) = (self.a.signum(), self.b.signum());
+        if sa == sb || sb == 0 {
+            sa
+        } else if sa == 0 || 2 * self.b * self.b > self.a * self.a {
+            sb
+        } else {
+            sa
+        }
+    }
+
+    fn abs(self) -> Self {
+        if self.signum() < 0 {
+            -self
+        } else {
+            self
+        }
+    }
+}
+
+impl Add for Surd {
+    type Output = Surd;
+
+    fn add(self, other: Surd) -> Surd {
+        let k = self.k.max(other.k);
+        Surd::new(
+            (self.a << (k - self.k)) + (other.a << (k - other.k)),
+            (self.b << (k - self.k)) + (other.b << (k - other.k)),
+            k,
+        )
+    }
+}
+
+impl Neg for Surd {
+    type Output = Surd;
+
+    fn neg(self) -> Surd {
+        Surd::new(-self.a, -self.b, self.k)
+    }
+}
+
+impl Sub for Surd {
+    type Output = Surd;
+
+    fn sub(self, other: Surd) -> Surd {
+        self + -other
+    }
+}
+
+impl Mul for Surd {
+    type Output = Surd;
+
+    fn mul(self, other: Surd) -> Surd {
+        Surd::new(
+            self.a * other.a + 2 * self.b * other.b,
+            self.a * other.b + self.b * other.a,
+            self.k + other.k,
+        )
+    }
 }
 
-fn calculate_p2(data: &Vec<(u8, i64)>) -> i64 {
-    let mut wp_x: i64 = 10;
-    let mut wp_y: i64 = 1;
-    let mut ship_x: i64 = 0;
-    let mut ship_y: i64 = 0;
+impl fmt::Display for Surd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let root = match self.b {
+            1 => "√2".to_string(),
+            -1 => "-√2".to_string(),
+            b => format!("{}√2", b),
+        };
+        let numerator = match (self.a, self.b) {
+            (a, 0) => a.to_string(),
+            (0, _) => root,
+            (a, b) if b < 0 => format!("{}-{}", a, &root[1..]),
+            (a, _) => format!("{}+{}", a, root),
+        };
 
-    for (command, n) in data {
+        match self.k {
+            0 => write!(f, "{}", numerator),
+            k if self.a != 0 && self.b != 0 => write!(f, "({})/{}", numerator, 1u128 << k),
+            k => write!(f, "{}/{}", numerator, 1u128 << k),
+        }
+    }
+}
+
+/// The arithmetic `navigate` needs for positions.
+trait Coordinate: Copy + Add<Output = Self> + Mul<Output = Self> {
+    fn from_int(n: i64) -> Self;
+    fn to_f64(self) -> f64;
+}
+
+impl Coordinate for i64 {
+    fn from_int(n: i64) -> Self {
+        n
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Coordinate for f64 {
+    fn from_int(n: i64) -> Self {
+        n as f64
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+}
+
+impl Coordinate for Surd {
+    fn from_int(n: i64) -> Self {
+        Surd::new(n.into(), 0, 0)
+    }
+
+    fn to_f64(self) -> f64 {
+        (self.a as f64 + self.b as f64 * 2f64.sqrt()) / (1u128 << self.k) as f64
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Mode {
+    /// Part 1: N/S/E/W move the ship, F moves it along its heading.
+    Ship,
+    /// Part 2: N/S/E/W move the waypoint, F moves the ship towards it.
+    Waypoint,
+}
+
+/// Follows the instructions, turning with `rotate` which is given a vector
+/// and a number of degrees anticlockwise. Returns the ship's final position.
+fn navigate<C: Coordinate>(
+    data: &[(u8, i64)],
+    mode: Mode,
+    rotate: impl Fn((C, C), i64) -> Result<(C, C), String>,
+) -> Result<(C, C), String> {
+    navigate_with(data, mode, rotate, |_, _| {})
+}
+
+/// Like `navigate`, calling `on_step` with the ship's position and its
+/// heading or waypoint at the start and after every instruction.
+fn navigate_with<C: Coordinate>(
+    data: &[(u8, i64)],
+    mode: Mode,
+    rotate: impl Fn((C, C), i64) -> Result<(C, C), String>,
+    mut on_step: impl FnMut((C, C), (C, C)),
+) -> Result<(C, C), String> {
+    let int = C::from_int;
+    let mut ship = (int(0), int(0));
+    // The ship's heading in part 1, the waypoint in part 2.
+    let mut heading = match mode {
+        Mode::Ship => (int(1), int(0)),
+        Mode::Waypoint => (int(10), int(1)),
+    };
+
+    on_step(ship, heading);
+    for &(command, n) in data {
+        let moved = match mode {
+            Mode::Ship => &mut ship,
+            Mode::Waypoint => &mut heading,
+        };
         match command {
-            b'N' => wp_y += n,
-            b'S' => wp_y -= n,
-            b'E' => wp_x += n,
-            b'W' => wp_x -= n,
-            b'L' => {
-                (wp_x, wp_y) = match n.rem_euclid(360) {
-                    0 => (wp_x, wp_y),
-                    90 => (-wp_y, wp_x),
-                    180 => (-wp_x, -wp_y),
-                    270 => (wp_y, -wp_x),
-                    _ => panic!("invalid dir"),
-                };
-            }
-            b'R' => {
-                (wp_x, wp_y) = match n.rem_euclid(360) {
-                    0 => (wp_x, wp_y),
-                    90 => (wp_y, -wp_x),
-                    180 => (-wp_x, -wp_y),
-                    270 => (-wp_y, wp_x),
-                    _ => panic!("invalid dir"),
-                };
+            b'N' => moved.1 = moved.1 + int(n),
+            b'S' => moved.1 = moved.1 + int(-n),
+            b'E' => moved.0 = moved.0 + int(n),
+            b'W' => moved.0 = moved.0 + int(-n),
+            b'L' | b'R' => {
+                let degrees = if command == b'L' { n } else { -n };
+                heading = rotate(heading, degrees)
+                    .map_err(|e| format!("{}{}: {}", command as char, n, e))?;
             }
-            b'F' => {
-                ship_x += wp_x * n;
-                ship_y += wp_y * n;
-            }
-            _ => panic!("invalid command"),
+            b'F' => ship = (ship.0 + heading.0 * int(n), ship.1 + heading.1 * int(n)),
+            _ => return Err(format!("invalid command '{}'", command as char)),
         }
+        on_step(ship, heading);
     }
 
-    ship_x.abs() + ship_y.abs()
+    Ok(ship)
+}
+
+fn rotate_right_angles((x, y): (i64, i64), degrees: i64) -> Result<(i64, i64), String> {
+    match degrees.rem_euclid(360) {
+        0 => Ok((x, y)),
+        90 => Ok((-y, x)),
+        180 => Ok((-x, -y)),
+        270 => Ok((y, -x)),
+        _ => Err("not a multiple of 90 degrees, try --arithmetic exact or float".to_string()),
+    }
+}
+
+fn rotate_exact((x, y): (Surd, Surd), degrees: i64) -> Result<(Surd, Surd), String> {
+    if degrees % 45 != 0 {
+        return Err("not a multiple of 45 degrees, try --arithmetic float".to_string());
+    }
+    let eighths = degrees.rem_euclid(360) / 45;
+    let (x, y) = match eighths / 2 {
+        0 => (x, y),
+        1 => (-y, x),
+        2 => (-x, -y),
+        _ => (y, -x),
+    };
+    match eighths % 2 {
+        0 => Ok((x, y)),
+        _ => Ok(((x - y).times_sqrt_half(), (x + y).times_sqrt_half())),
+    }
+}
+
+/// Rounds `value` to a whole number if it's within `tolerance` of one.
+fn snap(value: f64, tolerance: f64) -> f64 {
+    let rounded = value.round();
+    if (value - rounded).abs() <= tolerance {
+        rounded
+    } else {
+        value
+    }
+}
+
+/// Turns by any angle, snapping the result so that right angles stay exact
+/// and rounding errors don't build up over many turns.
+fn rotate_float(tolerance: f64) -> impl Fn((f64, f64), i64) -> Result<(f64, f64), String> {
+    move |(x, y), degrees| {
+        let (sin, cos) = (degrees as f64).to_radians().sin_cos();
+        Ok((
+            snap(x * cos - y * sin, tolerance),
+            snap(x * sin + y * cos, tolerance),
+        ))
+    }
+}
+
+fn calculate_p1(data: &[(u8, i64)]) -> i64 {
+    let (x, y) =
+        navigate(data, Mode::Ship, rotate_right_angles).unwrap_or_else(|e| panic!("p1: {}", e));
+    x.abs() + y.abs()
+}
+
+fn calculate_p2(data: &[(u8, i64)]) -> i64 {
+    let (x, y) =
+        navigate(data, Mode::Waypoint, rotate_right_angles).unwrap_or_else(|e| panic!("p2: {}", e));
+    x.abs() + y.abs()
+}
+
+fn distance_exact(data: &[(u8, i64)], mode: Mode) -> Result<Surd, String> {
+    let (x, y) = navigate(data, mode, rotate_exact)?;
+    Ok(x.abs() + y.abs())
+}
+
+fn distance_float(data: &[(u8, i64)], mode: Mode, tolerance: f64) -> Result<f64, String> {
+    let (x, y) = navigate(data, mode, rotate_float(tolerance))?;
+    Ok(snap(x.abs() + y.abs(), tolerance))
+}
+
+/// Where the ship was at the start and after each instruction.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Step {
+    ship: (f64, f64),
+    /// Relative to the ship, in part 2 only.
+    waypoint: Option<(f64, f64)>,
+}
+
+fn trajectory<C: Coordinate>(
+    data: &[(u8, i64)],
+    mode: Mode,
+    rotate: impl Fn((C, C), i64) -> Result<(C, C), String>,
+) -> Result<Vec<Step>, String> {
+    let mut steps = vec![];
+    navigate_with(data, mode, rotate, |ship, heading| {
+        steps.push(Step {
+            ship: (ship.0.to_f64(), ship.1.to_f64()),
+            waypoint: (mode == Mode::Waypoint).then(|| (heading.0.to_f64(), heading.1.to_f64())),
+        })
+    })?;
+    Ok(steps)
+}
+
+/// Furthest Manhattan distance the ship got from where it started.
+fn max_distance(steps: &[Step]) -> f64 {
+    steps
+        .iter()
+        .map(|step| step.ship.0.abs() + step.ship.1.abs())
+        .fold(0.0, f64::max)
+}
+
+/// Length of the ship's path, in straight lines between steps.
+fn total_distance(steps: &[Step]) -> f64 {
+    steps
+        .windows(2)
+        .map(|w| (w[1].ship.0 - w[0].ship.0).hypot(w[1].ship.1 - w[0].ship.1))
+        .sum()
+}
+
+fn export_csv(data: &[(u8, i64)], steps: &[Step]) -> String {
+    let mut out = String::from("step,instruction,ship_x,ship_y");
+    if steps[0].waypoint.is_some() {
+        out.push_str(",waypoint_x,waypoint_y");
+    }
+    out.push('\n');
+
+    for (idx, step) in steps.iter().enumerate() {
+        let instruction = match idx {
+            0 => String::new(),
+            _ => format!("{}{}", data[idx - 1].0 as char, data[idx - 1].1),
+        };
+        out.push_str(&format!(
+            "{},{},{},{}",
+            idx, instruction, step.ship.0, step.ship.1
+        ));
+        if let Some((x, y)) = step.waypoint {
+            out.push_str(&format!(",{},{}", x, y));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+const EARTH_RADIUS: f64 = 6_371_000.0;
+
+/// Latitude and longitude of a position `scale` metres per unit north and
+/// east of `origin`, treating the earth as flat around it. That breaks down
+/// at the poles, where longitude is undefined, so reaching one is an error.
+fn to_lat_lon((x, y): (f64, f64), origin: (f64, f64), scale: f64) -> Result<(f64, f64), String> {
+    let lat = origin.0 + (y * scale / EARTH_RADIUS).to_degrees();
+    if lat.abs() >= 90.0 || origin.0.abs() >= 90.0 {
+        return Err(format!("path reaches a pole at latitude {}", lat));
+    }
+    let lon = origin.1 + (x * scale / (EARTH_RADIUS * origin.0.to_radians().cos())).to_degrees();
+    // Going far enough east or west comes back round the other side.
+    let lon = (lon + 180.0).rem_euclid(360.0) - 180.0;
+    Ok((lat, lon))
+}
+
+fn export_geojson(steps: &[Step], origin: (f64, f64), scale: f64) -> Result<String, String> {
+    let coordinates = steps
+        .iter()
+        .map(|step| {
+            let (lat, lon) = to_lat_lon(step.ship, origin, scale)?;
+            Ok(format!("[{}, {}]", lon, lat))
+        })
+        .collect::<Result<Vec<String>, String>>()?;
+
+    Ok(format!(
+        r#"{{
+  "type": "Feature",
+  "properties": {{
+    "max_distance": {},
+    "total_distance": {}
+  }},
+  "geometry": {{
+    "type": "LineString",
+    "coordinates": [
+      {}
+    ]
+  }}
+}}
+"#,
+        max_distance(steps) * scale,
+        total_distance(steps) * scale,
+        coordinates.join(",\n      ")
+    ))
+}
+
+fn export_svg(steps: &[Step]) -> String {
+    // SVG's y axis points down, so north is -y (written 0 - y so that it
+    // never comes out as -0).
+    let ship: Vec<(f64, f64)> = steps.iter().map(|s| (s.ship.0, 0.0 - s.ship.1)).collect();
+    let waypoint: Vec<(f64, f64)> = steps
+        .iter()
+        .filter_map(|s| s.waypoint.map(|w| (s.ship.0 + w.0, 0.0 - (s.ship.1 + w.1))))
+        .collect();
+
+    let all = || ship.iter().chain(waypoint.iter());
+    let min_x = all().map(|p| p.0).fold(f64::INFINITY, f64::min);
+    let max_x = all().map(|p| p.0).fold(f64::NEG_INFINITY, f64::max);
+    let min_y = all().map(|p| p.1).fold(f64::INFINITY, f64::min);
+    let max_y = all().map(|p| p.1).fold(f64::NEG_INFINITY, f64::max);
+    let margin = ((max_x - min_x).max(max_y - min_y) / 20.0).max(1.0);
+
+    let points = |path: &[(f64, f64)]| {
+        path.iter()
+            .map(|(x, y)| format!("{},{}", x, y))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    let mut out = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}" width="800">
+"#,
+        min_x - margin,
+        min_y - margin,
+        max_x - min_x + 2.0 * margin,
+        max_y - min_y + 2.0 * margin
+    );
+    if !waypoint.is_empty() {
+        out.push_str(&format!(
+            r#"  <polyline fill="none" stroke="orange" stroke-dasharray="4" vector-effect="non-scaling-stroke" points="{}"/>
+"#,
+            points(&waypoint)
+        ));
+    }
+    out.push_str(&format!(
+        r#"  <polyline fill="none" stroke="steelblue" stroke-width="2" vector-effect="non-scaling-stroke" points="{}"/>
+  <circle cx="0" cy="0" r="{}" fill="black"/>
+</svg>
+"#,
+        points(&ship),
+        margin / 4.0
+    ));
+    out
 }
 
 fn main() {
     let args = Cli::parse();
     let raw_inp = fs::read_to_string(args.input).expect("can't open input file");
     let data = parse(&raw_inp);
-    let p1 = calculate_p1(&data);
-    let p2 = calculate_p2(&data);
+
+    if args.export.is_some() || args.stats {
+        let mode = match args.part {
+            1 => Mode::Ship,
+            _ => Mode::Waypoint,
+        };
+        let steps = match args.arithmetic {
+            Arithmetic::Integer => trajectory(&data, mode, rotate_right_angles),
+            Arithmetic::Exact => trajectory(&data, mode, rotate_exact),
+            Arithmetic::Float => trajectory(&data, mode, rotate_float(args.tolerance)),
+        }
+        .unwrap_or_else(|e| panic!("{}", e));
+
+        if args.stats {
+            println!("max distance from origin: {}", max_distance(&steps));
+            println!("total distance travelled: {}", total_distance(&steps));
+        }
+        if let Some(format) = args.export {
+            let out = match format {
+                ExportFormat::Csv => export_csv(&data, &steps),
+                ExportFormat::Geojson => export_geojson(&steps, args.origin, args.scale)
+                    .unwrap_or_else(|e| panic!("{}", e)),
+                ExportFormat::Svg => export_svg(&steps),
+            };
+            fs::write(args.output.unwrap(), out).expect("can't write output");
+        }
+        return;
+    }
+
+    let [p1, p2] = [Mode::Ship, Mode::Waypoint].map(|mode| match args.arithmetic {
+        Arithmetic::Integer if mode == Mode::Ship => calculate_p1(&data).to_string(),
+        Arithmetic::Integer => calculate_p2(&data).to_string(),
+        Arithmetic::Exact => distance_exact(&data, mode)
+            .unwrap_or_else(|e| panic!("{}", e))
+            .to_string(),
+        Arithmetic::Float => distance_float(&data, mode, args.tolerance)
+            .unwrap_or_else(|e| panic!("{}", e))
+            .to_string(),
+    });
     println!("{}\n{}", p1, p2);
 }
 
@@ -114,6 +577,126 @@ mod tests {
         assert_eq!(calculate_p2(&parse(TEST_DATA)), 286);
     }
 
+    #[test]
+    fn test_arithmetic_modes_agree() {
+        let data = parse(TEST_DATA);
+        for (mode, expected) in [(Mode::Ship, 25), (Mode::Waypoint, 286)] {
+            assert_eq!(distance_exact(&data, mode), Ok(Surd::new(expected, 0, 0)));
+            assert_eq!(distance_float(&data, mode, 1e-9), Ok(expected as f64));
+        }
+    }
+
+    #[test]
+    fn test_exact_diagonals() {
+        let data = parse("R45\nF10\nL90\nF3");
+        let distance = distance_exact(&data, Mode::Ship).unwrap();
+        assert_eq!(distance, Surd::new(0, 10, 0));
+        assert_eq!(distance.to_string(), "10√2");
+
+        // The waypoint (10, 1) turned 45° left is (9√2/2, 11√2/2).
+        let distance = distance_exact(&parse("L45\nF1"), Mode::Waypoint).unwrap();
+        assert_eq!(distance.to_string(), "10√2");
+        assert_eq!(Surd::new(2, 1, 1).to_string(), "(2+√2)/2");
+        assert_eq!(Surd::new(0, -6, 3).to_string(), "-3√2/4");
+
+        assert_eq!(Surd::new(3, -2, 0).signum(), 1);
+        assert_eq!(Surd::new(2, -2, 0).abs(), Surd::new(-2, 2, 0));
+        assert_eq!(
+            distance_exact(&parse("R30"), Mode::Ship),
+            Err("R30: not a multiple of 45 degrees, try --arithmetic float".to_string())
+        );
+        assert!(navigate(&parse("R45"), Mode::Ship, rotate_right_angles).is_err());
+    }
+
+    #[test]
+    fn test_float_turns() {
+        let data = parse("L30\nF2\nR60\nF2");
+        let (x, y) = navigate(&data, Mode::Ship, rotate_float(1e-9)).unwrap();
+        assert!((x - 2.0 * 3f64.sqrt()).abs() < 1e-9);
+        assert!(y.abs() < 1e-9);
+
+        // Without snapping, right angles pick up rounding errors.
+        let data = parse("L90\nF1");
+        let (x, _) = navigate(&data, Mode::Ship, rotate_float(0.0)).unwrap();
+        assert_ne!(x, 0.0);
+        let (x, _) = navigate(&data, Mode::Ship, rotate_float(1e-9)).unwrap();
+        assert_eq!(x, 0.0);
+    }
+
+    #[test]
+    fn test_trajectory() {
+        let data = parse(TEST_DATA);
+        let steps = trajectory(&data, Mode::Ship, rotate_right_angles).unwrap();
+        let ship: Vec<(f64, f64)> = steps.iter().map(|s| s.ship).collect();
+        assert_eq!(
+            ship,
+            [
+                (0.0, 0.0),
+                (10.0, 0.0),
+                (10.0, 3.0),
+                (17.0, 3.0),
+                (17.0, 3.0),
+                (17.0, -8.0)
+            ]
+        );
+        assert_eq!(max_distance(&steps), 25.0);
+        assert_eq!(total_distance(&steps), 31.0);
+
+        let steps = trajectory(&data, Mode::Waypoint, rotate_exact).unwrap();
+        assert_eq!(steps[0].waypoint, Some((10.0, 1.0)));
+        assert_eq!(steps[4].waypoint, Some((4.0, -10.0)));
+        assert_eq!(steps[5].ship, (214.0, -72.0));
+    }
+
+    #[test]
+    fn test_export_csv() {
+        let data = parse(TEST_DATA);
+        let steps = trajectory(&data, Mode::Waypoint, rotate_right_angles).unwrap();
+        let csv = export_csv(&data, &steps);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(
+            lines[0],
+            "step,instruction,ship_x,ship_y,waypoint_x,waypoint_y"
+        );
+        assert_eq!(lines[1], "0,,0,0,10,1");
+        assert_eq!(lines[2], "1,F10,100,10,10,1");
+    }
+
+    #[test]
+    fn test_export_geojson() {
+        let steps = trajectory(&parse("N10\nE10"), Mode::Ship, rotate_right_angles).unwrap();
+        let geojson = export_geojson(&steps, (60.0, 5.0), 1000.0).unwrap();
+        assert!(geojson.contains(r#""type": "LineString""#));
+        assert!(geojson.contains(r#""total_distance": 20000"#));
+        assert!(geojson.contains("[5, 60]"));
+
+        // 10km is about 0.09 degrees of latitude, and twice that in longitude
+        // at 60 degrees north.
+        let (lat, lon) = to_lat_lon((10.0, 10.0), (60.0, 5.0), 1000.0).unwrap();
+        assert!((lat - 60.0899).abs() < 1e-4);
+        assert!((lon - 5.1799).abs() < 1e-4);
+        assert_eq!(parse_origin("51.5, -0.1"), Ok((51.5, -0.1)));
+
+        // About 3300km north of 60 degrees is past the pole.
+        assert!(export_geojson(&steps, (60.0, 5.0), 400_000.0).is_err());
+        assert!(to_lat_lon((0.0, 0.0), (-90.0, 0.0), 1.0).is_err());
+
+        // About 1.8 degrees east of 179 degrees is across the date line.
+        let (_, lon) = to_lat_lon((200.0, 0.0), (0.0, 179.0), 1000.0).unwrap();
+        assert!((lon + 179.2013).abs() < 1e-4);
+        assert!(Cli::try_parse_from(["2020_12", "-i", "x", "--origin", "-33.9,151.2"]).is_ok());
+    }
+
+    #[test]
+    fn test_export_svg() {
+        let steps = trajectory(&parse(TEST_DATA), Mode::Ship, rotate_right_angles).unwrap();
+        let svg = export_svg(&steps);
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains(r#"points="0,0 10,0 10,-3 17,-3 17,-3 17,8""#));
+        assert!(!svg.contains("orange"));
+    }
+
     #[test]
     fn test_p1_real() {
         assert_eq!(calculate_p1(&parse(REAL_DATA)), 582);
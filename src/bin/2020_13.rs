@@ -1,11 +1,40 @@
+use ahash::AHashMap;
 use clap::Parser;
-use modinverse::modinverse;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
 use std::fs;
 
 #[derive(Parser)]
 struct Cli {
     #[clap(short, long)]
     input: String,
+
+    /// Time to run queries from, instead of the timestamp in the input
+    #[clap(long)]
+    after: Option<i64>,
+
+    /// Print the next N departures of each bus
+    #[clap(long)]
+    next: Option<usize>,
+
+    /// Print a departure board of the next N departures of any bus
+    #[clap(long)]
+    timetable: Option<usize>,
+
+    /// Find the first time at least this many buses depart within --window minutes
+    #[clap(long, requires = "window")]
+    at_least: Option<usize>,
+
+    #[clap(long)]
+    window: Option<i64>,
+
+    /// Find times when these buses depart at these offsets, as bus:offset,...
+    #[clap(long)]
+    subset: Option<String>,
+
+    /// How many times to print for --subset
+    #[clap(long, default_value_t = 1)]
+    count: usize,
 }
 
 fn parse(raw_inp: &str) -> (i64, Vec<Option<i64>>) {
@@ -29,31 +58,248 @@ fn calculate_p1(ts: i64, data: &[Option<i64>]) -> i64 {
     bus * (bus - (ts % bus))
 }
 
-/// chinese remainder theorem.
-fn calculate_p2(data: &[Option<i64>]) -> i64 {
-    let moduli: Vec<(i64, i64)> = data
+/// Returns (g, x) where g = gcd(a, b) and a * x ≡ g (mod b).
+fn extended_gcd(a: i128, b: i128) -> (i128, i128) {
+    let (mut old_r, mut r) = (a, b);
+    let (mut old_x, mut x) = (1, 0);
+    while r != 0 {
+        let q = old_r / r;
+        (old_r, r) = (r, old_r - q * r);
+        (old_x, x) = (x, old_x - q * x);
+    }
+    (old_r, old_x)
+}
+
+/// Generalised chinese remainder theorem: finds t with t ≡ residue (mod
+/// modulus) for every congruence, merging them one at a time so the moduli
+/// needn't be coprime. Residues may be negative. Returns the smallest
+/// non-negative t and the period the solutions repeat with.
+fn crt(congruences: impl IntoIterator<Item = (i128, i128)>) -> Result<(i128, i128), String> {
+    let mut solution = (0, 1);
+    for (residue, modulus) in congruences {
+        if modulus <= 0 {
+            return Err(format!("bus ids must be positive, not {}", modulus));
+        }
+        let (r1, m1) = solution;
+        let r2 = residue.rem_euclid(modulus);
+
+        let (g, inverse) = extended_gcd(m1, modulus);
+        let diff = r2 - r1;
+        if diff % g != 0 {
+            return Err("no timestamp exists".to_string());
+        }
+
+        // t = r1 + m1 * k, where m1 * k ≡ diff (mod modulus).
+        let step = modulus / g;
+        let k = (diff / g)
+            .rem_euclid(step)
+            .checked_mul(inverse.rem_euclid(step))
+            .ok_or("timestamps overflow i128")?
+            .rem_euclid(step);
+        let lcm = m1.checked_mul(step).ok_or("timestamps overflow i128")?;
+        solution = (r1 + m1 * k, lcm);
+    }
+    Ok(solution)
+}
+
+/// First timestamp where each bus departs as many minutes after it as its
+/// position in the list.
+fn calculate_p2(data: &[Option<i64>]) -> Result<i128, String> {
+    let congruences = data
         .iter()
         .enumerate()
-        .filter(|(_, n)| n.is_some())
-        .map(|(a, n)| (n.unwrap() - (a as i64), n.unwrap()))
+        .filter_map(|(offset, bus)| bus.map(|n| (-(offset as i128), i128::from(n))));
+    crt(congruences).map(|(ts, _)| ts)
+}
+
+/// The ids of the buses in service. Fails on an id which isn't positive, as
+/// that bus would never depart.
+fn buses(data: &[Option<i64>]) -> Result<Vec<i64>, String> {
+    data.iter()
+        .filter_map(|&b| b)
+        .map(|b| match b {
+            1.. => Ok(b),
+            _ => Err(format!("bus ids must be positive, not {}", b)),
+        })
+        .collect()
+}
+
+/// Every departure of any of the buses at or after a time, in order.
+struct Departures {
+    upcoming: BinaryHeap<Reverse<(i64, i64)>>,
+}
+
+impl Departures {
+    fn new(buses: &[i64], after: i64) -> Self {
+        let upcoming = buses
+            .iter()
+            .map(|&bus| Reverse((after + (-after).rem_euclid(bus), bus)))
+            .collect();
+        Departures { upcoming }
+    }
+}
+
+impl Iterator for Departures {
+    type Item = (i64, i64);
+
+    fn next(&mut self) -> Option<(i64, i64)> {
+        let Reverse((time, bus)) = self.upcoming.pop()?;
+        self.upcoming.push(Reverse((time + bus, bus)));
+        Some((time, bus))
+    }
+}
+
+/// The first `count` departures of each bus at or after `after`.
+fn next_departures(
+    data: &[Option<i64>],
+    after: i64,
+    count: usize,
+) -> Result<Vec<(i64, Vec<i64>)>, String> {
+    let departures = buses(data)?
+        .into_iter()
+        .map(|bus| {
+            let times = Departures::new(&[bus], after)
+                .take(count)
+                .map(|(time, _)| time)
+                .collect();
+            (bus, times)
+        })
         .collect();
+    Ok(departures)
+}
 
-    let prod: i64 = moduli.iter().map(|(_, n)| n).product();
+fn format_board(board: &[(i64, i64)], after: i64) -> String {
+    let mut out = format!("{:>8}  {:>6}  {:>4}\n", "time", "wait", "bus");
+    for &(time, bus) in board {
+        out.push_str(&format!("{:>8}  {:>6}  {:>4}\n", time, time - after, bus));
+    }
+    out
+}
 
-    let result: i64 = moduli
-        .iter()
-        .map(|&(a, n)| a * (prod / n) * (modinverse(prod / n, n).unwrap()))
-        .sum();
+/// First time at or after `after` from which at least `k` different buses
+/// depart within the next `window` minutes. Every bus departs at multiples of
+/// the product of their ids, so this always finds an answer if there are
+/// enough buses. A bus listed more than once still only counts once.
+fn busy_window(data: &[Option<i64>], after: i64, k: usize, window: i64) -> Result<i64, String> {
+    let mut buses = buses(data)?;
+    buses.sort_unstable();
+    buses.dedup();
+    if k > buses.len() {
+        return Err(format!("there are only {} buses", buses.len()));
+    }
+    if window < 1 {
+        return Err("window must be at least a minute".to_string());
+    }
+    if k == 0 {
+        return Ok(after);
+    }
 
-    result % prod
+    let mut in_window: VecDeque<(i64, i64)> = VecDeque::new();
+    let mut counts: AHashMap<i64, usize> = AHashMap::default();
+    for (time, bus) in Departures::new(&buses, after) {
+        // Drop departures too early to share a window with this one.
+        while let Some(&(first, first_bus)) = in_window.front() {
+            if time < first + window {
+                break;
+            }
+            in_window.pop_front();
+            let count = counts.get_mut(&first_bus).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&first_bus);
+            }
+        }
+
+        in_window.push_back((time, bus));
+        *counts.entry(bus).or_default() += 1;
+        if counts.len() >= k {
+            return Ok(after.max(time - window + 1));
+        }
+    }
+    unreachable!("departures never run out")
+}
+
+fn parse_subset(s: &str) -> Result<Vec<(i64, i64)>, String> {
+    s.split(',')
+        .map(|part| {
+            let (bus, offset) = part
+                .split_once(':')
+                .ok_or_else(|| format!("expected bus:offset, got '{}'", part))?;
+            let bus = bus.trim().parse().or(Err("can't parse bus"))?;
+            let offset = offset.trim().parse().or(Err("can't parse offset"))?;
+            Ok((bus, offset))
+        })
+        .collect()
+}
+
+/// The first `count` timestamps at or after `after` when each bus in
+/// `subset` departs its offset (which may be negative) minutes later.
+fn subset_timestamps(
+    data: &[Option<i64>],
+    subset: &[(i64, i64)],
+    after: i64,
+    count: usize,
+) -> Result<Vec<i128>, String> {
+    if let Some((bus, _)) = subset.iter().find(|(bus, _)| !data.contains(&Some(*bus))) {
+        return Err(format!("bus {} isn't in the timetable", bus));
+    }
+
+    let congruences = subset
+        .iter()
+        .map(|&(bus, offset)| (-i128::from(offset), i128::from(bus)));
+    let (ts, period) = crt(congruences)?;
+    let behind = (i128::from(after) - ts).max(0);
+    let first = ts + (behind + period - 1) / period * period;
+    Ok((0..count as i128).map(|i| first + i * period).collect())
 }
 
 fn main() {
     let args = Cli::parse();
     let raw_inp = fs::read_to_string(args.input).expect("can't open input file");
     let (ts, data) = parse(&raw_inp);
+
+    let after = args.after.unwrap_or(ts);
+    let queries = [args.next, args.timetable, args.at_least];
+    if queries.iter().any(Option::is_some) || args.subset.is_some() {
+        if let Some(count) = args.next {
+            match next_departures(&data, after, count) {
+                Ok(departures) => {
+                    for (bus, times) in departures {
+                        let times: Vec<String> = times.iter().map(|t| t.to_string()).collect();
+                        println!("{}: {}", bus, times.join(" "));
+                    }
+                }
+                Err(e) => println!("{}", e),
+            }
+        }
+        if let Some(count) = args.timetable {
+            match buses(&data) {
+                Ok(buses) => {
+                    let board: Vec<(i64, i64)> =
+                        Departures::new(&buses, after).take(count).collect();
+                    print!("{}", format_board(&board, after));
+                }
+                Err(e) => println!("{}", e),
+            }
+        }
+        if let Some(k) = args.at_least {
+            let window = args.window.unwrap();
+            match busy_window(&data, after, k, window) {
+                Ok(time) => println!("{} buses within {} minutes from {}", k, window, time),
+                Err(e) => println!("{}", e),
+            }
+        }
+        if let Some(subset) = args.subset {
+            let subset = parse_subset(&subset).unwrap_or_else(|e| panic!("invalid subset: {}", e));
+            match subset_timestamps(&data, &subset, after, args.count) {
+                Ok(times) => times.iter().for_each(|t| println!("{}", t)),
+                Err(e) => println!("{}", e),
+            }
+        }
+        return;
+    }
     let p1 = calculate_p1(ts, &data);
-    let p2 = calculate_p2(&data);
+    let p2 = calculate_p2(&data).unwrap_or_else(|e| panic!("p2: {}", e));
     println!("{}\n{}", p1, p2);
 }
 
@@ -73,7 +319,104 @@ mod tests {
     #[test]
     fn test_p2_example() {
         let (_, data) = parse(TEST_DATA);
-        assert_eq!(calculate_p2(&data), 1068781);
+        assert_eq!(calculate_p2(&data), Ok(1068781));
+    }
+
+    #[test]
+    fn test_crt_not_coprime() {
+        assert_eq!(crt([(0, 6), (-2, 4)]), Ok((6, 12)));
+        assert_eq!(calculate_p2(&parse("0\n6,x,4").1), Ok(6));
+        assert_eq!(
+            calculate_p2(&parse("0\n6,4").1),
+            Err("no timestamp exists".to_string())
+        );
+    }
+
+    #[test]
+    fn test_crt_negative_and_large() {
+        assert_eq!(crt([(-1, 5), (-12, 7)]), Ok((9, 35)));
+        assert_eq!(crt([]), Ok((0, 1)));
+
+        // Product of the moduli is around 10^27, far beyond i64.
+        let congruences = [(1, 1_000_000_007), (-2, 998_244_353), (3, 1_000_000_009)];
+        let (ts, period) = crt(congruences).unwrap();
+        assert_eq!(period, 1_000_000_007 * 998_244_353 * 1_000_000_009);
+        for (residue, modulus) in congruences {
+            assert_eq!(ts.rem_euclid(modulus), residue.rem_euclid(modulus));
+        }
+
+        let huge = (1i128 << 100) + 1;
+        assert_eq!(
+            crt([(0, huge), (1, huge + 2)]),
+            Err("timestamps overflow i128".to_string())
+        );
+        assert!(crt([(0, 0)]).is_err());
+    }
+
+    #[test]
+    fn test_next_departures() {
+        let (ts, data) = parse(TEST_DATA);
+        let next = next_departures(&data, ts, 2).unwrap();
+        assert_eq!(next[0], (7, vec![945, 952]));
+        assert_eq!(next[2], (59, vec![944, 1003]));
+        assert_eq!(next_departures(&data, 945, 1).unwrap()[0], (7, vec![945]));
+
+        let buses = buses(&data).unwrap();
+        let board: Vec<(i64, i64)> = Departures::new(&buses, ts).take(3).collect();
+        assert_eq!(board, [(944, 59), (945, 7), (949, 13)]);
+        assert_eq!(
+            format_board(&board[..1], ts),
+            "    time    wait   bus\n     944       5    59\n"
+        );
+    }
+
+    #[test]
+    fn test_busy_window() {
+        let (ts, data) = parse(TEST_DATA);
+        assert_eq!(busy_window(&data, ts, 2, 1), Ok(988));
+        assert_eq!(busy_window(&data, ts, 3, 5), Ok(948));
+        assert_eq!(busy_window(&data, ts, 3, 10), Ok(940));
+        assert_eq!(busy_window(&data, 0, 5, 1), Ok(0));
+        assert_eq!(
+            busy_window(&data, ts, 6, 10),
+            Err("there are only 5 buses".to_string())
+        );
+
+        // 7 and 13 only share a minute at multiples of 91.
+        let data = parse("0\n7,13").1;
+        assert_eq!(busy_window(&data, 1, 2, 1), Ok(91));
+        assert_eq!(busy_window(&data, 1, 2, 2), Ok(13));
+
+        let data = parse("939\n7,7,13").1;
+        assert_eq!(busy_window(&data, 939, 2, 100), Ok(939));
+        assert_eq!(
+            busy_window(&data, 939, 3, 100),
+            Err("there are only 2 buses".to_string())
+        );
+
+        let data = parse("939\n7,0,13").1;
+        let error = "bus ids must be positive, not 0".to_string();
+        assert_eq!(busy_window(&data, 939, 2, 100), Err(error.clone()));
+        assert_eq!(next_departures(&data, 939, 1), Err(error));
+    }
+
+    #[test]
+    fn test_subset_timestamps() {
+        let (ts, data) = parse(TEST_DATA);
+        let subset = parse_subset("7:0,13:1").unwrap();
+        assert_eq!(subset_timestamps(&data, &subset, 0, 1), Ok(vec![77]));
+        assert_eq!(
+            subset_timestamps(&data, &subset, ts, 2),
+            Ok(vec![987, 1078])
+        );
+
+        let subset = parse_subset("13:-1").unwrap();
+        assert_eq!(subset_timestamps(&data, &subset, 0, 2), Ok(vec![1, 14]));
+        assert_eq!(
+            subset_timestamps(&data, &[(5, 0)], 0, 1),
+            Err("bus 5 isn't in the timetable".to_string())
+        );
+        assert!(parse_subset("7").is_err());
     }
 
     #[test]
@@ -85,6 +428,6 @@ mod tests {
     #[test]
     fn test_p2_real() {
         let (_, data) = parse(REAL_DATA);
-        assert_eq!(calculate_p2(&data), 939490236001473);
+        assert_eq!(calculate_p2(&data), Ok(939490236001473));
     }
 }
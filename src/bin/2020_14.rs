@@ -1,125 +1,350 @@
 use ahash::AHashMap;
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt;
 use std::fs;
+use std::io::{self, BufWriter, Write};
 
 #[derive(Parser)]
 struct Cli {
     #[clap(short, long)]
     input: String,
+
+    /// Number of bits in masks, values and addresses
+    #[clap(long, default_value_t = 36, value_parser = clap::value_parser!(u32).range(1..=64))]
+    width: u32,
+
+    /// Print every address and value in memory after the program runs
+    #[clap(long, value_enum)]
+    dump: Option<DumpFormat>,
+
+    /// Print the program tidied up instead of running it
+    #[clap(long)]
+    format: bool,
+
+    /// Which puzzle's decoder to use with --dump
+    #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=2))]
+    part: u8,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DumpFormat {
+    Decimal,
+    /// Zero-padded to the word width
+    Hex,
+}
+
+/// Which bits of a word a mask sets, clears or leaves floating.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Mask {
+    width: u32,
+    ones: u64,
+    zeros: u64,
+    floating: u64,
+}
+
+impl Mask {
+    /// The mask in effect before the first mask instruction: leaves every
+    /// bit alone in part 1 and has nothing floating in part 2.
+    const NONE: Mask = Mask {
+        width: 0,
+        ones: 0,
+        zeros: 0,
+        floating: 0,
+    };
+}
+
+impl fmt::Display for Mask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for bit in (0..self.width).rev().map(|b| 1 << b) {
+            let c = match bit {
+                _ if self.ones & bit != 0 => '1',
+                _ if self.zeros & bit != 0 => '0',
+                _ => 'X',
+            };
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
 }
 
-struct Instruction {
-    mask: Option<[u8; 36]>,
-    mem: Option<(u64, u64)>,
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Instruction {
+    Mask(Mask),
+    Mem { address: u64, value: u64 },
 }
 
-fn parse(raw_inp: &str) -> Vec<Instruction> {
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::Mask(mask) => write!(f, "mask = {}", mask),
+            Instruction::Mem { address, value } => write!(f, "mem[{}] = {}", address, value),
+        }
+    }
+}
+
+fn parse_mask(s: &str, width: u32) -> Result<Mask, String> {
+    if s.len() != width as usize {
+        return Err(format!("mask has {} bits, expected {}", s.len(), width));
+    }
+
+    let mut mask = Mask {
+        width,
+        ..Mask::NONE
+    };
+    for (idx, c) in s.bytes().enumerate() {
+        let bit = 1 << (s.len() - idx - 1);
+        match c {
+            b'1' => mask.ones |= bit,
+            b'0' => mask.zeros |= bit,
+            b'X' => mask.floating |= bit,
+            _ => return Err(format!("invalid mask character '{}'", c as char)),
+        }
+    }
+    Ok(mask)
+}
+
+fn parse_line(line: &str, width: u32) -> Result<Instruction, String> {
+    let fits = |n: u64| width == 64 || n >> width == 0;
+    let (cmd, data) = line
+        .split_once(" = ")
+        .ok_or_else(|| format!("can't parse instruction '{}'", line))?;
+
+    if cmd == "mask" {
+        return Ok(Instruction::Mask(parse_mask(data, width)?));
+    }
+
+    let address = cmd
+        .strip_prefix("mem[")
+        .and_then(|a| a.strip_suffix(']'))
+        .ok_or_else(|| format!("can't parse instruction '{}'", line))?;
+    let address: u64 = address
+        .parse()
+        .map_err(|_| format!("invalid address '{}'", address))?;
+    let value: u64 = data
+        .parse()
+        .map_err(|_| format!("invalid value '{}'", data))?;
+
+    if !fits(address) {
+        return Err(format!("address {} doesn't fit in {} bits", address, width));
+    }
+    if !fits(value) {
+        return Err(format!("value {} doesn't fit in {} bits", value, width));
+    }
+    Ok(Instruction::Mem { address, value })
+}
+
+/// Parses a program for a machine with `width`-bit words, skipping blank
+/// lines.
+fn parse(raw_inp: &str, width: u32) -> Result<Vec<Instruction>, String> {
     raw_inp
-        .trim()
-        .split('\n')
-        .filter_map(|line| line.trim().split_once(" = "))
-        .map(|(cmd, data)| {
-            if cmd == "mask" {
-                Instruction {
-                    mask: Some(
-                        data.bytes()
-                            .collect::<Vec<u8>>()
-                            .try_into()
-                            .expect("into array failed"),
-                    ),
-                    mem: None,
-                }
-            } else {
-                let loc: u64 = cmd
-                    .trim_start_matches("mem[")
-                    .trim_end_matches(']')
-                    .parse()
-                    .expect("invalid memory location");
-
-                let mem_data: u64 = data.parse().expect("invalid mem data");
-
-                Instruction {
-                    mask: None,
-                    mem: Some((loc, mem_data)),
-                }
-            }
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(idx, line)| {
+            parse_line(line.trim(), width).map_err(|e| format!("line {}: {}", idx + 1, e))
         })
         .collect()
 }
 
-fn calculate_p1(data: &[Instruction]) -> u64 {
+/// The program in the same format `parse` reads.
+fn format_program(data: &[Instruction]) -> String {
+    data.iter().map(|inst| format!("{}\n", inst)).collect()
+}
+
+/// Runs the program with masks applied to values.
+fn run_p1(data: &[Instruction]) -> AHashMap<u64, u64> {
     let mut mem: AHashMap<u64, u64> = AHashMap::with_capacity(data.len());
-    let mut current_mask: [u8; 36] = [0; 36];
+    let mut current_mask = Mask::NONE;
 
     for inst in data {
-        if let Some(mask) = inst.mask {
-            current_mask = mask;
-        } else if let Some((mem_location, mem_data)) = inst.mem {
-            let mut masked_data = mem_data;
-
-            current_mask
-                .iter()
-                .enumerate()
-                .for_each(|(idx, m)| match m {
-                    b'1' => {
-                        masked_data |= 1 << (36 - idx - 1);
-                    }
-                    b'0' => {
-                        masked_data &= !(1 << (36 - idx - 1));
-                    }
-                    _ => {}
-                });
-
-            mem.insert(mem_location, masked_data);
+        match *inst {
+            Instruction::Mask(mask) => current_mask = mask,
+            Instruction::Mem { address, value } => {
+                mem.insert(address, (value | current_mask.ones) & !current_mask.zeros);
+            }
         }
     }
+    mem
+}
 
-    mem.values().sum()
+fn calculate_p1(data: &[Instruction]) -> u128 {
+    run_p1(data).values().map(|&v| u128::from(v)).sum()
 }
 
-fn calculate_p2(data: &[Instruction]) -> u64 {
-    let mut mem: AHashMap<u64, u64> = AHashMap::with_capacity(65536);
-    let mut current_mask = [0u8; 36];
+/// The addresses which match `fixed` on every bit outside `floating`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct AddressSet {
+    fixed: u64,
+    floating: u64,
+}
 
-    for inst in data {
-        if let Some(mask) = inst.mask {
-            current_mask = mask;
-        } else if let Some((mem_location, mem_data)) = inst.mem {
-            let mut masked_addr = mem_location;
-
-            current_mask
-                .iter()
-                .enumerate()
-                .filter(|&(_, &m)| m == b'1')
-                .for_each(|(idx, _)| {
-                    masked_addr |= 1 << (36 - idx - 1);
-                });
-
-            let mut addresses: Vec<u64> = vec![masked_addr];
-
-            current_mask
-                .iter()
-                .enumerate()
-                .filter(|&(_, &m)| m == b'X')
-                .for_each(|(idx, _)| {
-                    addresses = addresses
-                        .iter()
-                        .flat_map(|a| [a | 1 << (36 - idx - 1), a & (!(1 << (36 - idx - 1)))])
-                        .collect();
-                });
-
-            addresses.iter().for_each(|&a| {
-                mem.insert(a, mem_data);
+impl AddressSet {
+    fn len(&self) -> u128 {
+        1 << self.floating.count_ones()
+    }
+
+    /// Every address in the set, in increasing order.
+    fn addresses(&self) -> impl Iterator<Item = u64> + '_ {
+        // Counts up through every subset of the floating bits.
+        let mut next = Some(0u64);
+        std::iter::from_fn(move || {
+            let subset = next?;
+            next = match subset.wrapping_sub(self.floating) & self.floating {
+                0 => None,
+                n => Some(n),
+            };
+            Some(self.fixed | subset)
+        })
+    }
+
+    fn overlaps(&self, other: &AddressSet) -> bool {
+        (self.fixed ^ other.fixed) & !self.floating & !other.floating == 0
+    }
+
+    /// Splits the addresses in `self` but not `other` into disjoint sets, one
+    /// for each bit which floats here but is fixed in `other`.
+    fn minus(&self, other: &AddressSet) -> Vec<AddressSet> {
+        let mut rest = *self;
+        let mut pieces = vec![];
+        let mut split_bits = self.floating & !other.floating;
+        while split_bits != 0 {
+            let bit = split_bits & split_bits.wrapping_neg();
+            split_bits ^= bit;
+
+            rest.floating &= !bit;
+            pieces.push(AddressSet {
+                fixed: rest.fixed | (!other.fixed & bit),
+                floating: rest.floating,
             });
+            rest.fixed |= other.fixed & bit;
         }
+        pieces
     }
-    mem.values().sum()
+}
+
+/// Memory written through floating addresses, as disjoint sets of addresses
+/// with the value last written to each. Overwriting part of a set splits off
+/// the rest, so no write is ever expanded into its individual addresses.
+#[derive(Default)]
+struct FloatingMemory {
+    sets: Vec<(AddressSet, u64)>,
+}
+
+impl FloatingMemory {
+    fn write(&mut self, addresses: AddressSet, value: u64) {
+        let mut sets = Vec::with_capacity(self.sets.len() + 1);
+        for &(set, old) in &self.sets {
+            if set.overlaps(&addresses) {
+                sets.extend(set.minus(&addresses).into_iter().map(|s| (s, old)));
+            } else {
+                sets.push((set, old));
+            }
+        }
+        sets.push((addresses, value));
+        self.sets = sets;
+    }
+
+    /// Every address written and its value, in address order. Each set's
+    /// addresses come out in order already, so they're merged lazily rather
+    /// than expanding every set at once.
+    fn cells(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        let mut sets: Vec<_> = self.sets.iter().map(|(set, _)| set.addresses()).collect();
+        let mut next: BinaryHeap<Reverse<(u64, usize)>> = sets
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(idx, addresses)| Some(Reverse((addresses.next()?, idx))))
+            .collect();
+        std::iter::from_fn(move || {
+            let Reverse((address, idx)) = next.pop()?;
+            if let Some(following) = sets[idx].next() {
+                next.push(Reverse((following, idx)));
+            }
+            Some((address, self.sets[idx].1))
+        })
+    }
+
+    fn sum(&self) -> u128 {
+        self.sets
+            .iter()
+            .map(|(set, value)| set.len() * u128::from(*value))
+            .sum()
+    }
+}
+
+/// Runs the program with masks applied to addresses.
+fn run_p2(data: &[Instruction]) -> FloatingMemory {
+    let mut mem = FloatingMemory::default();
+    let mut current_mask = Mask::NONE;
+
+    for inst in data {
+        match *inst {
+            Instruction::Mask(mask) => current_mask = mask,
+            Instruction::Mem { address, value } => {
+                let addresses = AddressSet {
+                    fixed: (address | current_mask.ones) & !current_mask.floating,
+                    floating: current_mask.floating,
+                };
+                mem.write(addresses, value);
+            }
+        }
+    }
+    mem
+}
+
+fn calculate_p2(data: &[Instruction]) -> u128 {
+    run_p2(data).sum()
+}
+
+/// Writes every address and value in memory, one per line, in the order
+/// given (which should be by address).
+fn dump(
+    mem: impl Iterator<Item = (u64, u64)>,
+    format: DumpFormat,
+    width: u32,
+    mut out: impl Write,
+) -> io::Result<()> {
+    let digits = width.div_ceil(4) as usize;
+    for (address, value) in mem {
+        match format {
+            DumpFormat::Decimal => writeln!(out, "mem[{}] = {}", address, value)?,
+            DumpFormat::Hex => writeln!(
+                out,
+                "mem[0x{:0digits$x}] = 0x{:0digits$x}",
+                address,
+                value,
+                digits = digits
+            )?,
+        }
+    }
+    out.flush()
 }
 
 fn main() {
     let args = Cli::parse();
     let raw_inp = fs::read_to_string(args.input).expect("can't open input file");
-    let data = parse(&raw_inp);
+    let data = parse(&raw_inp, args.width).unwrap_or_else(|e| panic!("{}", e));
+
+    if args.format {
+        print!("{}", format_program(&data));
+        return;
+    }
+
+    if let Some(format) = args.dump {
+        let out = BufWriter::new(io::stdout().lock());
+        match args.part {
+            1 => {
+                let mut mem: Vec<(u64, u64)> = run_p1(&data).into_iter().collect();
+                mem.sort_unstable();
+                dump(mem.into_iter(), format, args.width, out)
+            }
+            _ => dump(run_p2(&data).cells(), format, args.width, out),
+        }
+        .expect("can't write memory dump");
+        return;
+    }
+
     let p1 = calculate_p1(&data);
     let p2 = calculate_p2(&data);
     println!("{}\n{}", p1, p2);
@@ -143,21 +368,155 @@ mem[26] = 1";
 
     #[test]
     fn test_p1_example() {
-        assert_eq!(calculate_p1(&parse(TEST_DATA_P1)), 165);
+        assert_eq!(calculate_p1(&parse(TEST_DATA_P1, 36).unwrap()), 165);
     }
 
     #[test]
     fn test_p2_example() {
-        assert_eq!(calculate_p2(&parse(TEST_DATA_P2)), 208);
+        assert_eq!(calculate_p2(&parse(TEST_DATA_P2, 36).unwrap()), 208);
+    }
+
+    #[test]
+    fn test_address_set_minus() {
+        let a = AddressSet {
+            fixed: 0b0000,
+            floating: 0b0111,
+        };
+        let b = AddressSet {
+            fixed: 0b0010,
+            floating: 0b1001,
+        };
+        assert!(a.overlaps(&b));
+        let pieces = a.minus(&b);
+        assert_eq!(pieces.iter().map(|p| p.len()).sum::<u128>(), 6);
+        assert!(pieces.iter().all(|p| !p.overlaps(&b)));
+        assert!(!pieces[0].overlaps(&pieces[1]));
+    }
+
+    #[test]
+    fn test_p2_many_floating_bits() {
+        // 2^32 addresses, then half of them overwritten, then a disjoint
+        // block of 2^17 in the upper half of memory.
+        let program = format!(
+            "mask = 0000{}
+mem[0] = 5
+mask = 0000{}1
+mem[0] = 1
+mask = 1000XX{}
+mem[0] = 3",
+            "X".repeat(32),
+            "X".repeat(31),
+            "0X".repeat(15)
+        );
+        let expected = 5 * (1 << 31) + (1 << 31) + 3 * (1 << 17);
+        assert_eq!(calculate_p2(&parse(&program, 36).unwrap()), expected);
+    }
+
+    #[test]
+    fn test_width() {
+        let program = parse("mask = X1X0\nmem[3] = 15\nmem[2] = 0", 4).unwrap();
+        assert_eq!(calculate_p1(&program), 14 + 4);
+        assert_eq!(
+            parse("mask = X1X0", 36).err(),
+            Some("line 1: mask has 4 bits, expected 36".to_string())
+        );
+        assert_eq!(
+            parse("mask = XX\nmem[1] = 4", 2).err(),
+            Some("line 2: value 4 doesn't fit in 2 bits".to_string())
+        );
+
+        // Values of all ones can't be summed in 64 bits.
+        let program = format!(
+            "mask = {}\nmem[0] = {}\nmem[1] = {}",
+            "X".repeat(64),
+            u64::MAX,
+            u64::MAX
+        );
+        let program = parse(&program, 64).unwrap();
+        assert_eq!(calculate_p1(&program), 2 * u128::from(u64::MAX));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = |program: &str| parse(program, 4).unwrap_err();
+        assert_eq!(
+            err("mask = X1X0\n\nmask = X1Y0"),
+            "line 3: invalid mask character 'Y'"
+        );
+        assert_eq!(
+            err("mem[3] = 1\nmem[16] = 1"),
+            "line 2: address 16 doesn't fit in 4 bits"
+        );
+        assert_eq!(err("mem[3] = -1"), "line 1: invalid value '-1'");
+        assert_eq!(err("mem[x] = 1"), "line 1: invalid address 'x'");
+        assert_eq!(
+            err("mem[3]=1"),
+            "line 1: can't parse instruction 'mem[3]=1'"
+        );
+        assert_eq!(
+            err("reg[3] = 1"),
+            "line 1: can't parse instruction 'reg[3] = 1'"
+        );
+    }
+
+    #[test]
+    fn test_format_round_trip() {
+        for raw in [TEST_DATA_P1, TEST_DATA_P2] {
+            let program = parse(raw, 36).unwrap();
+            let formatted = format_program(&program);
+            assert_eq!(formatted, format!("{}\n", raw));
+            assert_eq!(parse(&formatted, 36), Ok(program));
+        }
+        assert_eq!(
+            format_program(&parse("  mask = 1X0X  \n\n mem[7] = 0", 4).unwrap()),
+            "mask = 1X0X\nmem[7] = 0\n"
+        );
+    }
+
+    #[test]
+    fn test_dump() {
+        let program = parse(TEST_DATA_P2, 36).unwrap();
+        let mem = run_p2(&program);
+        let cells: Vec<(u64, u64)> = mem.cells().collect();
+        assert_eq!(cells.len(), 10);
+        assert!(cells.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+        let mut out = vec![];
+        dump(mem.cells(), DumpFormat::Decimal, 36, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("mem[16] = 1\nmem[17] = 1\n"));
+        assert!(out.ends_with("mem[58] = 100\nmem[59] = 100\n"));
+
+        let mut mem: Vec<(u64, u64)> = run_p1(&parse(TEST_DATA_P1, 36).unwrap())
+            .into_iter()
+            .collect();
+        mem.sort_unstable();
+        let mut out = vec![];
+        dump(mem.into_iter(), DumpFormat::Hex, 36, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "mem[0x000000007] = 0x000000065\nmem[0x000000008] = 0x000000040\n"
+        );
+
+        // Far too many addresses to hold at once, but they stream in order.
+        let program = parse(
+            "mask = 0XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX\nmem[0] = 5\nmem[3] = 7",
+            36,
+        )
+        .unwrap();
+        let mem = run_p2(&program);
+        let mut cells = mem.cells();
+        assert_eq!(cells.next(), Some((0, 7)));
+        assert_eq!(cells.nth(1 << 20), Some(((1 << 20) + 1, 7)));
     }
 
     #[test]
     fn test_p1_real() {
-        assert_eq!(calculate_p1(&parse(REAL_DATA)), 13556564111697);
+        assert_eq!(calculate_p1(&parse(REAL_DATA, 36).unwrap()), 13556564111697);
     }
 
     #[test]
     fn test_p2_real() {
-        assert_eq!(calculate_p2(&parse(REAL_DATA)), 4173715962894);
+        assert_eq!(calculate_p2(&parse(REAL_DATA, 36).unwrap()), 4173715962894);
     }
 }
@@ -1,18 +1,60 @@
+use ahash::AHashMap;
 use bitvec::prelude::*;
-use clap::Parser;
+use clap::{ArgGroup, Parser};
 use mimalloc::MiMalloc;
+use std::fmt;
 use std::fs;
+use std::io::{self, Read, Write};
 
 #[global_allocator]
 static ALLOCATOR: MiMalloc = MiMalloc;
 
 #[derive(Parser)]
+#[clap(group(ArgGroup::new("target").multiple(true)))]
 struct Cli {
     #[clap(short, long)]
     input: String,
+
+    /// Turns to print the number spoken on, instead of the puzzle answers
+    #[clap(long, value_delimiter = ',', group = "target", value_parser = clap::value_parser!(u64).range(1..))]
+    turn: Vec<u64>,
+
+    /// Largest array to track numbers in, in bytes, before using a hash map
+    #[clap(long, default_value_t = DEFAULT_MEMORY_LIMIT)]
+    memory_limit: u64,
+
+    /// Play up to this turn, for --sequence and --stats
+    #[clap(long, group = "target", value_parser = clap::value_parser!(u64).range(1..))]
+    to: Option<u64>,
+
+    /// Write each turn up to --to and the number spoken on it here, as CSV
+    #[clap(long, requires = "to")]
+    sequence: Option<String>,
+
+    /// First turn to write to --sequence
+    #[clap(long, default_value_t = 1, requires = "sequence")]
+    from: u64,
+
+    /// Print statistics about every turn played
+    #[clap(long, requires = "target", conflicts_with = "restore")]
+    stats: bool,
+
+    /// Numbers to print the first turn spoken on for, with --stats
+    #[clap(long, value_delimiter = ',', requires = "stats")]
+    first: Vec<u64>,
+
+    /// Save the game to this file after the last turn asked for
+    #[clap(long, requires = "target")]
+    save: Option<String>,
+
+    /// Carry on from a game saved with --save
+    #[clap(long, requires = "target")]
+    restore: Option<String>,
 }
 
-fn parse(raw_inp: &str) -> Vec<u32> {
+const DEFAULT_MEMORY_LIMIT: u64 = 4 << 30;
+
+fn parse(raw_inp: &str) -> Vec<u64> {
     raw_inp
         .lines()
         .next()
@@ -22,54 +64,401 @@ fn parse(raw_inp: &str) -> Vec<u32> {
         .collect::<Vec<_>>()
 }
 
-fn populate_initial_state(data: &[u32], turns: u32) -> (Vec<u32>, BitVec<u32, Lsb0>) {
-    let mut spoken = vec![0; turns as usize];
-    let mut existence_sieve = bitvec![u32, Lsb0; 0; turns as usize];
+/// The turn each number was last spoken on.
+enum Spoken {
+    /// Indexed by number, with a bit per number saying whether it's been
+    /// spoken at all, which is much faster to check than the turns.
+    Dense {
+        turns: Vec<u32>,
+        seen: BitVec<u32, Lsb0>,
+    },
+    /// For games too long to keep an array for.
+    Sparse(AHashMap<u64, u64>),
+}
 
-    data.iter().zip(0..).for_each(|(&n, idx)| {
-        spoken[n as usize] = idx;
-        existence_sieve.set(n as usize, true);
-    });
+impl Spoken {
+    fn entries(&self) -> Vec<(u64, u64)> {
+        match self {
+            Spoken::Dense { turns, seen } => seen
+                .iter_ones()
+                .map(|n| (n as u64, u64::from(turns[n])))
+                .collect(),
+            Spoken::Sparse(map) => map.iter().map(|(&n, &turn)| (n, turn)).collect(),
+        }
+    }
 
-    (spoken, existence_sieve)
-}
+    /// Makes room for numbers below `capacity`, moving to a hash map if an
+    /// array would be bigger than `memory_limit` bytes or hold turns past
+    /// the range of u32.
+    fn reserve(&mut self, capacity: u64, memory_limit: u64) {
+        let Spoken::Dense { turns, seen } = self else {
+            return;
+        };
+        if capacity <= turns.len() as u64 {
+            return;
+        }
+
+        let bytes = capacity.saturating_mul(4) + capacity / 8;
+        if capacity <= u64::from(u32::MAX) && bytes <= memory_limit {
+            turns.resize(capacity as usize, 0);
+            seen.resize(capacity as usize, false);
+        } else {
+            *self = Spoken::Sparse(self.entries().into_iter().collect());
+        }
+    }
 
-fn simulate<const TURNS: u32>(data: &[u32]) -> u32 {
-    let mut last: u32 = data[data.len() - 1];
+    /// Needs room for `number` to have been reserved.
+    fn insert(&mut self, number: u64, turn: u64) {
+        match self {
+            Spoken::Dense { turns, seen } => {
+                turns[number as usize] = turn as u32;
+                seen.set(number as usize, true);
+            }
+            Spoken::Sparse(map) => {
+                map.insert(number, turn);
+            }
+        }
+    }
+}
 
-    let (mut spoken, mut existence_sieve) = populate_initial_state(data, TURNS);
+struct Game {
+    starting: Vec<u64>,
+    spoken: Spoken,
+    /// The last turn played, and the number spoken on it.
+    turn: u64,
+    last: u64,
+    memory_limit: u64,
+}
 
-    let start_turn: u32 = data.len().try_into().expect("num turns exceeded u32 range");
-    for turn in start_turn..TURNS {
-        let next = if existence_sieve[last as usize] {
-            turn - 1 - spoken[last as usize]
-        } else {
-            existence_sieve.set(last as usize, true);
-            0
+impl Game {
+    fn new(starting: &[u64], memory_limit: u64) -> Self {
+        let (&last, before) = starting.split_last().expect("no starting numbers");
+        let mut game = Game {
+            starting: starting.to_vec(),
+            spoken: Spoken::Dense {
+                turns: vec![],
+                seen: BitVec::new(),
+            },
+            turn: starting.len() as u64,
+            last,
+            memory_limit,
         };
-        spoken[last as usize] = turn - 1;
 
-        last = next;
+        let largest = starting.iter().max().unwrap();
+        game.spoken.reserve(largest + 1, memory_limit);
+        for (&n, turn) in before.iter().zip(1..) {
+            game.spoken.insert(n, turn);
+        }
+        game
+    }
+
+    /// Plays up to `turn` and returns the number spoken on it. Turns already
+    /// played can't be asked for again, except for the starting numbers.
+    fn play_until(&mut self, turn: u64) -> Result<u64, String> {
+        self.play_until_with(turn, |_, _| {})
+    }
+
+    /// Like `play_until`, but also passes every turn played and the number
+    /// spoken on it to `on_spoken`.
+    fn play_until_with(
+        &mut self,
+        turn: u64,
+        mut on_spoken: impl FnMut(u64, u64),
+    ) -> Result<u64, String> {
+        if turn == 0 {
+            return Err("turns start at 1".to_string());
+        }
+        if let Some(&n) = self.starting.get(turn as usize - 1) {
+            return Ok(n);
+        }
+        if turn < self.turn {
+            return Err(format!(
+                "turn {} was before turn {} where the game got to",
+                turn, self.turn
+            ));
+        }
+
+        // Every number after the starting ones is the gap between two turns,
+        // so is less than the turn it's spoken on.
+        self.spoken.reserve(turn, self.memory_limit);
+        match &mut self.spoken {
+            Spoken::Dense { turns, seen } => {
+                let mut last = self.last as u32;
+                for t in self.turn as u32..turn as u32 {
+                    let next = if seen[last as usize] {
+                        t - turns[last as usize]
+                    } else {
+                        seen.set(last as usize, true);
+                        0
+                    };
+                    turns[last as usize] = t;
+                    last = next;
+                    on_spoken(u64::from(t) + 1, u64::from(next));
+                }
+                self.last = u64::from(last);
+            }
+            Spoken::Sparse(map) => {
+                for t in self.turn..turn {
+                    self.last = match map.insert(self.last, t) {
+                        Some(previous) => t - previous,
+                        None => 0,
+                    };
+                    on_spoken(t + 1, self.last);
+                }
+            }
+        }
+        self.turn = turn;
+        Ok(self.last)
+    }
+
+    /// Writes the game as little-endian u64s: the starting numbers, the turn
+    /// and number last spoken, then every number with the turn it was last
+    /// spoken on.
+    fn save(&self, mut out: impl Write) -> io::Result<()> {
+        let entries = self.spoken.entries();
+        let mut words = vec![self.starting.len() as u64];
+        words.extend(&self.starting);
+        words.extend([self.turn, self.last, entries.len() as u64]);
+        words.extend(entries.iter().flat_map(|&(n, turn)| [n, turn]));
+
+        out.write_all(SAVE_MAGIC)?;
+        for word in words {
+            out.write_all(&word.to_le_bytes())?;
+        }
+        Ok(())
     }
 
-    last
+    fn restore(mut input: impl Read, memory_limit: u64) -> Result<Self, String> {
+        let mut buf = vec![];
+        input
+            .read_to_end(&mut buf)
+            .map_err(|e| format!("can't read saved game: {}", e))?;
+        let words = buf
+            .strip_prefix(SAVE_MAGIC)
+            .ok_or("not a saved game")?
+            .chunks(8)
+            .map(|c| c.try_into().map(u64::from_le_bytes))
+            .collect::<Result<Vec<u64>, _>>()
+            .or(Err("saved game is truncated"))?;
+
+        let mut words = words.into_iter();
+        let mut next = || words.next().ok_or("saved game is truncated");
+        let starting = (0..next()?)
+            .map(|_| next())
+            .collect::<Result<Vec<_>, _>>()?;
+        let (turn, last, count) = (next()?, next()?, next()?);
+        let entries = (0..count)
+            .map(|_| Ok((next()?, next()?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        if starting.is_empty() || turn < starting.len() as u64 {
+            return Err("saved game is corrupt".to_string());
+        }
+
+        let mut game = Game::new(&starting, memory_limit);
+        let largest = entries.iter().map(|&(n, _)| n).max().unwrap_or(0);
+        game.spoken.reserve(turn.max(largest + 1), memory_limit);
+        for (n, spoken_turn) in entries {
+            game.spoken.insert(n, spoken_turn);
+        }
+        (game.turn, game.last) = (turn, last);
+        Ok(game)
+    }
 }
 
-fn calculate_p1(data: &[u32]) -> u32 {
-    simulate::<2020>(data)
+const SAVE_MAGIC: &[u8] = b"MG15";
+
+/// Running statistics over the spoken sequence.
+#[derive(Debug, PartialEq)]
+struct Stats {
+    turns: u64,
+    zeros: u64,
+    largest: u64,
+    /// Numbers to look out for, with the turn each was first spoken on.
+    first: Vec<(u64, Option<u64>)>,
 }
 
-fn calculate_p2(data: &[u32]) -> u32 {
-    simulate::<30000000>(data)
+impl Stats {
+    fn new(watch: &[u64]) -> Self {
+        Stats {
+            turns: 0,
+            zeros: 0,
+            largest: 0,
+            first: watch.iter().map(|&n| (n, None)).collect(),
+        }
+    }
+
+    fn record(&mut self, turn: u64, number: u64) {
+        self.turns = turn;
+        self.zeros += u64::from(number == 0);
+        self.largest = self.largest.max(number);
+        for (n, first) in self.first.iter_mut() {
+            if *n == number && first.is_none() {
+                *first = Some(turn);
+            }
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "turns: {}", self.turns)?;
+        writeln!(
+            f,
+            "zeros: {} ({:.2}%)",
+            self.zeros,
+            100.0 * self.zeros as f64 / self.turns.max(1) as f64
+        )?;
+        write!(f, "largest: {}", self.largest)?;
+        for (n, first) in &self.first {
+            match first {
+                Some(turn) => write!(f, "\nfirst {}: turn {}", n, turn)?,
+                None => write!(f, "\nfirst {}: never", n)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Plays a new game up to `turn`, passing every turn from the first one on
+/// and the number spoken on it to `on_spoken`.
+fn play_from_start(
+    game: &mut Game,
+    turn: u64,
+    mut on_spoken: impl FnMut(u64, u64),
+) -> Result<u64, String> {
+    let len = game.starting.len() as u64;
+    for (&n, t) in game.starting.iter().zip(1..=turn.min(len)) {
+        on_spoken(t, n);
+    }
+    game.play_until_with(turn, on_spoken)
+}
+
+fn calculate_p1(data: &[u64]) -> u64 {
+    Game::new(data, DEFAULT_MEMORY_LIMIT)
+        .play_until(2020)
+        .unwrap()
+}
+
+fn calculate_p2(data: &[u64]) -> u64 {
+    Game::new(data, DEFAULT_MEMORY_LIMIT)
+        .play_until(30000000)
+        .unwrap()
+}
+
+/// Plays up to the last of `turns` and `to` in one pass, returning the
+/// number spoken on each of `turns`. Every turn up to `to`, or the last of
+/// `turns` if it's not given, is passed to `on_spoken`, including the starting
+/// numbers for a new game.
+fn play_turns(
+    game: &mut Game,
+    turns: &[u64],
+    to: Option<u64>,
+    mut on_spoken: impl FnMut(u64, u64),
+) -> Result<AHashMap<u64, u64>, String> {
+    let mut turns = turns.to_vec();
+    turns.sort_unstable();
+    turns.dedup();
+    let end = turns
+        .iter()
+        .copied()
+        .chain(to)
+        .max()
+        .ok_or("no turns to play")?;
+    let to = to.unwrap_or(end);
+
+    let mut answers = AHashMap::default();
+    let mut pending = turns.iter().peekable();
+    let mut on_turn = |turn, number| {
+        if pending.next_if_eq(&&turn).is_some() {
+            answers.insert(turn, number);
+        }
+        if turn <= to {
+            on_spoken(turn, number);
+        }
+    };
+    if game.turn == game.starting.len() as u64 {
+        play_from_start(game, end, &mut on_turn)
+    } else {
+        game.play_until_with(end, &mut on_turn)
+    }?;
+    Ok(answers)
 }
 
 fn main() {
     let args = Cli::parse();
     let raw_inp = fs::read_to_string(args.input).expect("can't open input file");
     let data = parse(&raw_inp);
-    let p1 = calculate_p1(&data);
-    let p2 = calculate_p2(&data);
-    println!("{}\n{}", p1, p2);
+
+    if args.turn.is_empty() && args.to.is_none() {
+        let p1 = calculate_p1(&data);
+        let p2 = calculate_p2(&data);
+        println!("{}\n{}", p1, p2);
+        return;
+    }
+
+    let mut game = match args.restore {
+        Some(path) => {
+            let file = fs::File::open(path).expect("can't open saved game");
+            let game = Game::restore(io::BufReader::new(file), args.memory_limit)
+                .unwrap_or_else(|e| panic!("{}", e));
+            if game.starting != data {
+                panic!("saved game has different starting numbers");
+            }
+            game
+        }
+        None => Game::new(&data, args.memory_limit),
+    };
+
+    let fresh = game.turn == data.len() as u64;
+    if !fresh
+        && (args.turn.iter().any(|&t| t <= game.turn)
+            || (args.sequence.is_some() && args.from <= game.turn))
+    {
+        panic!(
+            "turns before {} were played in the saved game",
+            game.turn + 1
+        );
+    }
+
+    let mut stats = Stats::new(&args.first);
+    let mut out = args.sequence.map(|path| {
+        let file = fs::File::create(path).expect("can't create sequence file");
+        let mut out = io::BufWriter::new(file);
+        writeln!(out, "turn,number").expect("can't write sequence file");
+        out
+    });
+    let recording = args.stats || out.is_some();
+    let on_spoken = |turn, number| {
+        if args.stats {
+            stats.record(turn, number);
+        }
+        if let Some(out) = out.as_mut().filter(|_| turn >= args.from) {
+            writeln!(out, "{},{}", turn, number).expect("can't write sequence file");
+        }
+    };
+    // Without anything to record the loop can skip the callback entirely.
+    let answers = if recording {
+        play_turns(&mut game, &args.turn, args.to, on_spoken)
+    } else {
+        play_turns(&mut game, &args.turn, args.to, |_, _| {})
+    }
+    .unwrap_or_else(|e| panic!("{}", e));
+
+    if let Some(mut out) = out {
+        out.flush().expect("can't write sequence file");
+    }
+    if args.stats {
+        println!("{}", stats);
+    }
+    for turn in &args.turn {
+        println!("{}: {}", turn, answers[turn]);
+    }
+
+    if let Some(path) = args.save {
+        let file = fs::File::create(path).expect("can't create save file");
+        game.save(io::BufWriter::new(file))
+            .expect("can't write save file");
+    }
 }
 
 #[cfg(test)]
@@ -157,6 +546,130 @@ mod tests {
         assert_eq!(calculate_p2(&parse(EXAMPLE7)), 362);
     }
 
+    #[test]
+    fn test_turns() {
+        let mut game = Game::new(&parse(EXAMPLE), DEFAULT_MEMORY_LIMIT);
+        let spoken: Vec<u64> = (1..=10).map(|t| game.play_until(t).unwrap()).collect();
+        assert_eq!(spoken, [0, 3, 6, 0, 3, 3, 1, 0, 4, 0]);
+        assert_eq!(game.play_until(2020), Ok(436));
+        assert_eq!(game.play_until(2), Ok(3));
+        assert!(game.play_until(100).is_err());
+        assert!(game.play_until(0).is_err());
+
+        // Repeated starting numbers count as having been spoken.
+        let mut game = Game::new(&[0, 0], DEFAULT_MEMORY_LIMIT);
+        assert_eq!(game.play_until(3), Ok(1));
+        assert_eq!(game.play_until(4), Ok(0));
+        assert_eq!(game.play_until(5), Ok(2));
+    }
+
+    #[test]
+    fn test_sparse_matches_dense() {
+        let data = parse(EXAMPLE3);
+        let mut dense = Game::new(&data, DEFAULT_MEMORY_LIMIT);
+        let mut sparse = Game::new(&data, 0);
+        assert!(matches!(sparse.spoken, Spoken::Sparse(_)));
+        for turn in [100, 2020, 50000] {
+            assert_eq!(dense.play_until(turn), sparse.play_until(turn));
+        }
+
+        // Switching over part way through.
+        let mut game = Game::new(&data, 40000);
+        assert_eq!(game.play_until(2020), Ok(10));
+        assert!(matches!(game.spoken, Spoken::Dense { .. }));
+        assert_eq!(game.play_until(50000), dense.play_until(50000));
+        assert!(matches!(game.spoken, Spoken::Sparse(_)));
+    }
+
+    #[test]
+    fn test_save_restore() {
+        let data = parse(EXAMPLE);
+        let mut game = Game::new(&data, DEFAULT_MEMORY_LIMIT);
+        game.play_until(1000).unwrap();
+        let mut saved = vec![];
+        game.save(&mut saved).unwrap();
+
+        for memory_limit in [DEFAULT_MEMORY_LIMIT, 0] {
+            let mut restored = Game::restore(saved.as_slice(), memory_limit).unwrap();
+            assert_eq!(restored.starting, data);
+            assert_eq!(restored.play_until(2020), Ok(436));
+        }
+
+        assert_eq!(
+            Game::restore(&saved[..saved.len() - 3], 0).err(),
+            Some("saved game is truncated".to_string())
+        );
+        assert_eq!(
+            Game::restore(&b"nope"[..], 0).err(),
+            Some("not a saved game".to_string())
+        );
+    }
+
+    #[test]
+    fn test_sequence() {
+        let mut game = Game::new(&parse(EXAMPLE), DEFAULT_MEMORY_LIMIT);
+        let mut spoken = vec![];
+        let last = play_from_start(&mut game, 10, |turn, n| spoken.push((turn, n)));
+        assert_eq!(last, Ok(0));
+        assert_eq!(
+            spoken,
+            (1..=10)
+                .zip([0, 3, 6, 0, 3, 3, 1, 0, 4, 0])
+                .collect::<Vec<_>>()
+        );
+
+        let mut game = Game::new(&parse(EXAMPLE), 0);
+        let mut spoken = vec![];
+        play_from_start(&mut game, 2, |turn, n| spoken.push((turn, n))).unwrap();
+        game.play_until_with(5, |turn, n| spoken.push((turn, n)))
+            .unwrap();
+        assert_eq!(spoken, [(1, 0), (2, 3), (4, 0), (5, 3)]);
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut game = Game::new(&parse(EXAMPLE), DEFAULT_MEMORY_LIMIT);
+        let mut stats = Stats::new(&[4, 3, 5]);
+        play_from_start(&mut game, 10, |turn, n| stats.record(turn, n)).unwrap();
+        assert_eq!(
+            stats,
+            Stats {
+                turns: 10,
+                zeros: 4,
+                largest: 6,
+                first: vec![(4, Some(9)), (3, Some(2)), (5, None)],
+            }
+        );
+        assert_eq!(
+            stats.to_string(),
+            "turns: 10\nzeros: 4 (40.00%)\nlargest: 6\nfirst 4: turn 9\nfirst 3: turn 2\nfirst 5: never"
+        );
+    }
+
+    #[test]
+    fn test_play_turns() {
+        let mut game = Game::new(&parse(EXAMPLE), DEFAULT_MEMORY_LIMIT);
+        let mut stats = Stats::new(&[4]);
+        let answers = play_turns(&mut game, &[10, 4], None, |t, n| stats.record(t, n)).unwrap();
+        assert_eq!(answers[&10], 0);
+        assert_eq!(answers[&4], 0);
+        assert_eq!(
+            stats,
+            Stats {
+                turns: 10,
+                zeros: 4,
+                largest: 6,
+                first: vec![(4, Some(9))],
+            }
+        );
+
+        let mut game = Game::new(&parse(EXAMPLE), DEFAULT_MEMORY_LIMIT);
+        let mut stats = Stats::new(&[]);
+        let answers = play_turns(&mut game, &[2020], Some(5), |t, n| stats.record(t, n)).unwrap();
+        assert_eq!(answers[&2020], 436);
+        assert_eq!(stats.turns, 5);
+    }
+
     #[test]
     fn test_p1_real() {
         assert_eq!(calculate_p1(&parse(REAL_DATA)), 694);
@@ -1,20 +1,32 @@
-use ahash::AHashMap;
 use clap::Parser;
+use regex::Regex;
+use std::collections::VecDeque;
 use std::fs;
 
 #[derive(Parser)]
 struct Cli {
     #[clap(short, long)]
     input: String,
+
+    /// Print which column each field is in
+    #[clap(long)]
+    assignment: bool,
+
+    /// Fields to multiply on my ticket, by a prefix of their names or a regex
+    /// matched from the start
+    #[clap(long, default_value = "departure")]
+    fields: String,
+
+    /// Print every ticket decoded as JSON, along with the invalid ones
+    #[clap(long, conflicts_with = "assignment")]
+    decode: bool,
 }
 
-#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
 struct ValidityRules {
-    start_1: u64,
-    end_1: u64,
-    start_2: u64,
-    end_2: u64,
-    is_departure: bool,
+    name: String,
+    /// Inclusive ranges the value can be in.
+    ranges: Vec<(u64, u64)>,
 }
 
 fn parse(raw_inp: &str) -> (Vec<ValidityRules>, Vec<u64>, Vec<Vec<u64>>) {
@@ -29,16 +41,20 @@ fn parse(raw_inp: &str) -> (Vec<ValidityRules>, Vec<u64>, Vec<Vec<u64>>) {
         .lines()
         .map(|info| {
             let (name, values) = info.split_once(": ").expect("invalid format");
-            let (v1, v2) = values.split_once(" or ").expect("invalid format");
-            let (start_1, end_1) = v1.split_once('-').expect("invalid format");
-            let (start_2, end_2) = v2.split_once('-').expect("invalid format");
+            let ranges = values
+                .split(" or ")
+                .map(|range| {
+                    let (start, end) = range.split_once('-').expect("invalid format");
+                    (
+                        start.parse().expect("parse failed"),
+                        end.parse().expect("parse failed"),
+                    )
+                })
+                .collect();
 
             ValidityRules {
-                is_departure: name.starts_with("departure"),
-                start_1: start_1.parse().expect("parse failed"),
-                end_1: end_1.parse().expect("parse failed"),
-                start_2: start_2.parse().expect("parse failed"),
-                end_2: end_2.parse().expect("parse failed"),
+                name: name.to_string(),
+                ranges,
             }
         })
         .collect();
@@ -63,7 +79,9 @@ fn parse(raw_inp: &str) -> (Vec<ValidityRules>, Vec<u64>, Vec<Vec<u64>>) {
 }
 
 fn rule_matches(rule: &ValidityRules, n: u64) -> bool {
-    (rule.start_1 <= n && rule.end_1 >= n) || (rule.start_2 <= n && rule.end_2 >= n)
+    rule.ranges
+        .iter()
+        .any(|&(start, end)| start <= n && end >= n)
 }
 
 fn any_rule_matches(rules: &[ValidityRules], value: u64) -> bool {
@@ -74,11 +92,16 @@ fn ticket_matches_any_rule(rules: &[ValidityRules], ticket: &[u64]) -> bool {
     ticket.iter().all(|&val| any_rule_matches(rules, val))
 }
 
-fn ticket_error_rate(rules: &[ValidityRules], ticket: &[u64]) -> u64 {
+fn invalid_values(rules: &[ValidityRules], ticket: &[u64]) -> Vec<u64> {
     ticket
         .iter()
-        .map(|&val| if any_rule_matches(rules, val) { 0 } else { val })
-        .sum()
+        .copied()
+        .filter(|&val| !any_rule_matches(rules, val))
+        .collect()
+}
+
+fn ticket_error_rate(rules: &[ValidityRules], ticket: &[u64]) -> u64 {
+    invalid_values(rules, ticket).iter().sum()
 }
 
 fn calculate_p1(rules: &[ValidityRules], _my_ticket: &[u64], other_tickets: &[Vec<u64>]) -> u64 {
@@ -88,22 +111,129 @@ fn calculate_p1(rules: &[ValidityRules], _my_ticket: &[u64], other_tickets: &[Ve
         .sum()
 }
 
-fn calculate_p2(rules: &[ValidityRules], my_ticket: &[u64], other_tickets: &[Vec<u64>]) -> u64 {
+/// Finds a maximum matching of fields to columns with Hopcroft–Karp, where
+/// `candidates[field]` lists the columns that field could be in.
+fn hopcroft_karp(candidates: &[Vec<usize>], columns: usize) -> Vec<Option<usize>> {
+    let mut field_column = vec![None; candidates.len()];
+    let mut column_field = vec![None; columns];
+
+    loop {
+        // Layer the fields by how far they are along alternating paths from
+        // an unmatched field, stopping once a free column is reachable.
+        let mut layer = vec![usize::MAX; candidates.len()];
+        let mut queue: VecDeque<usize> = (0..candidates.len())
+            .filter(|&field| field_column[field].is_none())
+            .collect();
+        for &field in &queue {
+            layer[field] = 0;
+        }
+        let mut found_free_column = false;
+        while let Some(field) = queue.pop_front() {
+            for &column in &candidates[field] {
+                match column_field[column] {
+                    None => found_free_column = true,
+                    Some(next) if layer[next] == usize::MAX => {
+                        layer[next] = layer[field] + 1;
+                        queue.push_back(next);
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        if !found_free_column {
+            return field_column;
+        }
+
+        for field in 0..candidates.len() {
+            if field_column[field].is_none() {
+                augment(
+                    field,
+                    candidates,
+                    &mut layer,
+                    &mut field_column,
+                    &mut column_field,
+                );
+            }
+        }
+    }
+}
+
+/// Looks for a shortest augmenting path from `field` through the layers and
+/// flips it if there is one.
+fn augment(
+    field: usize,
+    candidates: &[Vec<usize>],
+    layer: &mut [usize],
+    field_column: &mut [Option<usize>],
+    column_field: &mut [Option<usize>],
+) -> bool {
+    for &column in &candidates[field] {
+        let extends = match column_field[column] {
+            None => true,
+            Some(next) => {
+                layer[next] == layer[field] + 1
+                    && augment(next, candidates, layer, field_column, column_field)
+            }
+        };
+        if extends {
+            field_column[field] = Some(column);
+            column_field[column] = Some(field);
+            return true;
+        }
+    }
+    // Dead end, so don't try this field again in this phase.
+    layer[field] = usize::MAX;
+    false
+}
+
+/// The column each field is in, indexed like the rules.
+#[derive(Debug, PartialEq)]
+struct Assignment {
+    columns: Vec<usize>,
+    /// Other ways to assign every field, if the tickets don't pin them all
+    /// down. Between them these move every field that isn't fixed.
+    alternatives: Vec<Vec<usize>>,
+}
+
+impl Assignment {
+    fn field_map<'a>(&self, rules: &'a [ValidityRules]) -> Vec<(&'a str, usize)> {
+        rules
+            .iter()
+            .zip(&self.columns)
+            .map(|(rule, &column)| (rule.name.as_str(), column))
+            .collect()
+    }
+}
+
+fn assign_fields(
+    rules: &[ValidityRules],
+    my_ticket: &[u64],
+    other_tickets: &[Vec<u64>],
+) -> Result<Assignment, String> {
+    let num_fields = my_ticket.len();
+    if let Some(idx) = other_tickets
+        .iter()
+        .position(|ticket| ticket.len() != num_fields)
+    {
+        return Err(format!(
+            "nearby ticket {} has {} values, but yours has {}",
+            idx + 1,
+            other_tickets[idx].len(),
+            num_fields
+        ));
+    }
+
     let valid_other_tickets: Vec<_> = other_tickets
         .iter()
         .filter(|ticket| ticket_matches_any_rule(rules, ticket))
         .collect();
 
-    let num_fields = valid_other_tickets[0].len();
-
-    let mut assignments: AHashMap<&ValidityRules, usize> = AHashMap::default();
-
-    // Which rules could be valid in which positions?
-    let mut possible_assignments: Vec<Vec<&ValidityRules>> = (0..num_fields)
-        .map(|field| {
-            rules
-                .iter()
-                .filter(|rule| {
+    // Which positions could each rule be valid in?
+    let candidates: Vec<Vec<usize>> = rules
+        .iter()
+        .map(|rule| {
+            (0..num_fields)
+                .filter(|&field| {
                     valid_other_tickets
                         .iter()
                         .all(|ticket| rule_matches(rule, ticket[field]))
@@ -112,29 +242,172 @@ fn calculate_p2(rules: &[ValidityRules], my_ticket: &[u64], other_tickets: &[Vec
         })
         .collect();
 
-    // Repeatedly find a rule which is the only valid rule
-    // in a particular position, assign that rule to that
-    // position and remove it from being valid in other positions
-    while assignments.len() != rules.len() {
-        for field in 0..num_fields {
-            if possible_assignments[field].len() == 1 {
-                let key = possible_assignments[field][0];
-                let new_assignments = possible_assignments
-                    .iter()
-                    .map(|assignments| assignments.iter().filter(|&x| x != &key).copied().collect())
-                    .collect::<Vec<Vec<_>>>();
-
-                assignments.insert(key, field);
-                possible_assignments = new_assignments;
+    let matching = hopcroft_karp(&candidates, num_fields);
+    let unmatched: Vec<_> = rules
+        .iter()
+        .zip(&matching)
+        .filter(|(_, column)| column.is_none())
+        .map(|(rule, _)| format!("'{}'", rule.name))
+        .collect();
+    if !unmatched.is_empty() {
+        return Err(format!(
+            "no way to give every field its own column, {} left over",
+            unmatched.join(", ")
+        ));
+    }
+    let columns: Vec<usize> = matching.into_iter().flatten().collect();
+
+    // A field's column is only fixed if nothing else works once it's ruled
+    // out for that field.
+    let mut alternatives: Vec<Vec<usize>> = vec![];
+    for (field, &column) in columns.iter().enumerate() {
+        let mut without = candidates.clone();
+        without[field].retain(|&c| c != column);
+        let other = hopcroft_karp(&without, num_fields);
+        if let Some(other) = other.into_iter().collect::<Option<Vec<_>>>() {
+            if !alternatives.contains(&other) {
+                alternatives.push(other);
             }
         }
     }
 
-    assignments
+    Ok(Assignment {
+        columns,
+        alternatives,
+    })
+}
+
+/// Matches field names starting with `pattern`, which can be a plain prefix
+/// or a regex.
+fn field_selector(pattern: &str) -> Result<Regex, String> {
+    Regex::new(&format!("^(?:{})", pattern))
+        .map_err(|e| format!("invalid --fields '{}': {}", pattern, e))
+}
+
+fn calculate_p2(
+    rules: &[ValidityRules],
+    my_ticket: &[u64],
+    other_tickets: &[Vec<u64>],
+    fields: &Regex,
+) -> Result<u64, String> {
+    let assignment = assign_fields(rules, my_ticket, other_tickets)?;
+
+    let selected: Vec<_> = (0..rules.len())
+        .filter(|&field| fields.is_match(&rules[field].name))
+        .collect();
+    let moved = selected.iter().find(|&&field| {
+        assignment
+            .alternatives
+            .iter()
+            .any(|other| other[field] != assignment.columns[field])
+    });
+    if let Some(&field) = moved {
+        return Err(format!(
+            "can't tell which column '{}' is in",
+            rules[field].name
+        ));
+    }
+
+    Ok(selected
+        .iter()
+        .map(|&field| my_ticket[assignment.columns[field]])
+        .product())
+}
+
+fn format_assignment(rules: &[ValidityRules], assignment: &Assignment) -> String {
+    let mut lines: Vec<String> = assignment
+        .field_map(rules)
+        .iter()
+        .map(|(name, column)| format!("{}: {}", name, column))
+        .collect();
+    for (i, other) in assignment.alternatives.iter().enumerate() {
+        let moved: Vec<String> = rules
+            .iter()
+            .zip(other.iter().zip(&assignment.columns))
+            .filter(|(_, (a, b))| a != b)
+            .map(|(rule, (column, _))| format!("{}: {}", rule.name, column))
+            .collect();
+        lines.push(format!("alternative {}: {}", i + 1, moved.join(", ")));
+    }
+    lines.join("\n")
+}
+
+/// The name and value of each field on a ticket, in the order they're written.
+fn decode<'a>(
+    rules: &'a [ValidityRules],
+    assignment: &Assignment,
+    ticket: &[u64],
+) -> Vec<(&'a str, u64)> {
+    let mut fields = assignment.field_map(rules);
+    fields.sort_by_key(|&(_, column)| column);
+    fields
+        .into_iter()
+        .map(|(name, column)| (name, ticket[column]))
+        .collect()
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_ticket(fields: &[(&str, u64)]) -> String {
+    let fields: Vec<String> = fields
         .iter()
-        .filter(|(k, _)| k.is_departure)
-        .map(|(_, &v)| my_ticket[v])
-        .product()
+        .map(|&(name, value)| format!("{}: {}", json_string(name), value))
+        .collect();
+    format!("{{{}}}", fields.join(", "))
+}
+
+/// My ticket and the valid nearby tickets decoded into fields, and the
+/// invalid nearby tickets with the values no rule allows.
+fn decode_tickets(
+    rules: &[ValidityRules],
+    my_ticket: &[u64],
+    other_tickets: &[Vec<u64>],
+) -> Result<String, String> {
+    let assignment = assign_fields(rules, my_ticket, other_tickets)?;
+    if !assignment.alternatives.is_empty() {
+        return Err("can't decode tickets, the fields could be in more than one order".to_string());
+    }
+
+    let mut nearby = vec![];
+    let mut invalid = vec![];
+    for (i, ticket) in other_tickets.iter().enumerate() {
+        let values = invalid_values(rules, ticket);
+        if values.is_empty() {
+            nearby.push(json_ticket(&decode(rules, &assignment, ticket)));
+        } else {
+            invalid.push(format!(
+                r#"{{"index": {}, "ticket": {:?}, "invalid_values": {:?}}}"#,
+                i, ticket, values
+            ));
+        }
+    }
+
+    Ok(format!(
+        r#"{{
+  "my_ticket": {},
+  "nearby_tickets": [
+    {}
+  ],
+  "invalid_tickets": [
+    {}
+  ]
+}}"#,
+        json_ticket(&decode(rules, &assignment, my_ticket)),
+        nearby.join(",\n    "),
+        invalid.join(",\n    ")
+    ))
 }
 
 fn main() {
@@ -142,7 +415,21 @@ fn main() {
     let raw_inp = fs::read_to_string(args.input).expect("can't open input file");
     let (rules, ticket, other_tickets) = parse(&raw_inp);
     let p1 = calculate_p1(&rules, &ticket, &other_tickets);
-    let p2 = calculate_p2(&rules, &ticket, &other_tickets);
+    if args.assignment {
+        let assignment =
+            assign_fields(&rules, &ticket, &other_tickets).unwrap_or_else(|e| panic!("{}", e));
+        println!("{}", format_assignment(&rules, &assignment));
+        return;
+    }
+    if args.decode {
+        let json =
+            decode_tickets(&rules, &ticket, &other_tickets).unwrap_or_else(|e| panic!("{}", e));
+        println!("{}", json);
+        return;
+    }
+    let fields = field_selector(&args.fields).unwrap_or_else(|e| panic!("{}", e));
+    let p2 =
+        calculate_p2(&rules, &ticket, &other_tickets, &fields).unwrap_or_else(|e| panic!("{}", e));
     println!("{}\n{}", p1, p2);
 }
 
@@ -152,6 +439,18 @@ mod tests {
 
     const EXAMPLE: &str = include_str!("../../inputs/examples/2020_16");
 
+    const EXAMPLE2: &str = "class: 0-1 or 4-19
+row: 0-5 or 8-19
+seat: 0-13 or 16-19
+
+your ticket:
+11,12,13
+
+nearby tickets:
+3,9,18
+15,1,5
+5,14,9";
+
     const REAL_DATA: &str = include_str!("../../inputs/real/2020_16");
 
     #[test]
@@ -163,7 +462,128 @@ mod tests {
     #[test]
     fn test_p2_example() {
         let (rules, ticket, other_tickets) = parse(&EXAMPLE);
-        assert_eq!(calculate_p2(&rules, &ticket, &other_tickets), 1);
+        assert_eq!(
+            calculate_p2(&rules, &ticket, &other_tickets, &departure()),
+            Ok(1)
+        );
+    }
+
+    fn departure() -> Regex {
+        field_selector("departure").unwrap()
+    }
+
+    #[test]
+    fn test_rules() {
+        let (rules, _, _) = parse(
+            "zone 1: 1-2\ndeparture \"x\": 5-5 or 7-8 or 10-20\n\nyour ticket:\n1,2\n\nnearby tickets:\n",
+        );
+        assert_eq!(rules[0].name, "zone 1");
+        assert_eq!(rules[0].ranges, [(1, 2)]);
+        assert_eq!(rules[1].name, "departure \"x\"");
+        assert_eq!(rules[1].ranges, [(5, 5), (7, 8), (10, 20)]);
+        assert!(rule_matches(&rules[1], 15));
+        assert!(!rule_matches(&rules[1], 6));
+    }
+
+    #[test]
+    fn test_fields() {
+        let (rules, ticket, other_tickets) = parse(EXAMPLE2);
+        let product = |pattern| {
+            calculate_p2(
+                &rules,
+                &ticket,
+                &other_tickets,
+                &field_selector(pattern).unwrap(),
+            )
+        };
+        assert_eq!(product("seat"), Ok(13));
+        assert_eq!(product("s"), Ok(13));
+        assert_eq!(product("eat"), Ok(1));
+        assert_eq!(product("class|row"), Ok(12 * 11));
+        assert_eq!(product(".*a"), Ok(12 * 13));
+        assert_eq!(product("row$"), Ok(11));
+        assert!(field_selector("(").is_err());
+    }
+
+    #[test]
+    fn test_decode() {
+        let (rules, ticket, other_tickets) = parse(EXAMPLE);
+        assert_eq!(
+            decode_tickets(&rules, &ticket, &other_tickets),
+            Ok(r#"{
+  "my_ticket": {"row": 7, "class": 1, "seat": 14},
+  "nearby_tickets": [
+    {"row": 7, "class": 3, "seat": 47}
+  ],
+  "invalid_tickets": [
+    {"index": 1, "ticket": [40, 4, 50], "invalid_values": [4]},
+    {"index": 2, "ticket": [55, 2, 20], "invalid_values": [55]},
+    {"index": 3, "ticket": [38, 6, 12], "invalid_values": [12]}
+  ]
+}"#
+            .to_string())
+        );
+        assert_eq!(json_string("a \"b\"\\\n"), r#""a \"b\"\\\u000a""#);
+    }
+
+    #[test]
+    fn test_assignment() {
+        let (rules, ticket, other_tickets) = parse(EXAMPLE2);
+        let assignment = assign_fields(&rules, &ticket, &other_tickets).unwrap();
+        assert!(assignment.alternatives.is_empty());
+        assert_eq!(
+            assignment.field_map(&rules),
+            [("class", 1), ("row", 0), ("seat", 2)]
+        );
+    }
+
+    #[test]
+    fn test_ambiguous_assignment() {
+        // Nothing tells class and row apart.
+        let raw = EXAMPLE2
+            .replace("class: 0-1 or 4-19", "class: 0-19 or 30-30")
+            .replace("row: 0-5 or 8-19", "row: 0-19 or 30-30");
+        let (rules, ticket, other_tickets) = parse(&raw);
+        let assignment = assign_fields(&rules, &ticket, &other_tickets).unwrap();
+        assert_eq!(assignment.alternatives.len(), 1);
+        assert_eq!(assignment.columns[2], 2);
+        assert_eq!(assignment.alternatives[0][2], 2);
+        assert_ne!(assignment.alternatives[0], assignment.columns);
+        let formatted = format_assignment(&rules, &assignment);
+        assert_eq!(formatted.lines().count(), 4);
+        assert!(formatted
+            .lines()
+            .last()
+            .unwrap()
+            .starts_with("alternative 1: "));
+
+        let raw = raw.replace("class", "departure class");
+        let (rules, ticket, other_tickets) = parse(&raw);
+        assert_eq!(
+            calculate_p2(&rules, &ticket, &other_tickets, &departure()),
+            Err("can't tell which column 'departure class' is in".to_string())
+        );
+    }
+
+    #[test]
+    fn test_impossible_assignment() {
+        // Class and row can only be in the first column.
+        let raw = EXAMPLE2
+            .replace("class: 0-1 or 4-19", "class: 3-3 or 15-15")
+            .replace("row: 0-5 or 8-19", "row: 3-3 or 15-15");
+        let (rules, ticket, other_tickets) = parse(&raw);
+        let error = assign_fields(&rules, &ticket, &other_tickets).unwrap_err();
+        assert!(error.starts_with("no way to give every field its own column"));
+    }
+
+    #[test]
+    fn test_short_ticket() {
+        let raw = EXAMPLE2.replace("15,1,5", "15,1");
+        let (rules, ticket, other_tickets) = parse(&raw);
+        assert_eq!(
+            assign_fields(&rules, &ticket, &other_tickets).unwrap_err(),
+            "nearby ticket 2 has 2 values, but yours has 3"
+        );
     }
 
     #[test]
@@ -175,6 +595,9 @@ mod tests {
     #[test]
     fn test_p2_real() {
         let (rules, ticket, other_tickets) = parse(&REAL_DATA);
-        assert_eq!(calculate_p2(&rules, &ticket, &other_tickets), 2843534243843);
+        assert_eq!(
+            calculate_p2(&rules, &ticket, &other_tickets, &departure()),
+            Ok(2843534243843)
+        );
     }
 }
@@ -1,4 +1,5 @@
 use ahash::{AHashMap, AHashSet};
+use bitvec::prelude::*;
 use clap::Parser;
 use std::fs;
 
@@ -6,6 +7,19 @@ use std::fs;
 struct Cli {
     #[clap(short, long)]
     input: String,
+
+    /// Dimension counts to simulate instead of the puzzles, printing the
+    /// answer for each
+    #[clap(long, value_delimiter = ',', value_parser = clap::value_parser!(u8).range(2..=8))]
+    dimensions: Vec<u8>,
+
+    #[clap(long, default_value_t = 6, requires = "dimensions")]
+    cycles: usize,
+
+    /// Neighbour counts that bring a cube to life and keep it alive, as
+    /// digits or separated by commas if any is above 9 (B3,12/S2,3)
+    #[clap(long, default_value = "B3/S23", value_parser = parse_rule, requires = "dimensions")]
+    rule: Rule,
 }
 
 fn parse(raw_inp: &str) -> AHashSet<(i32, i32)> {
@@ -27,132 +41,206 @@ fn parse(raw_inp: &str) -> AHashSet<(i32, i32)> {
     data
 }
 
-type Point3 = (i32, i32, i32);
-type Point4 = (i32, i32, i32, i32);
-type PointSet3 = AHashSet<Point3>;
-type PointSet4 = AHashSet<Point4>;
+/// Most neighbours a cube can have, in 8 dimensions.
+const MAX_NEIGHBOURS: usize = 3usize.pow(8) - 1;
 
-fn build_p1_activity_map(frame: &PointSet3) -> AHashMap<Point3, usize> {
-    let mut activity_map: AHashMap<Point3, usize> = AHashMap::with_capacity(frame.len() * 40);
+/// Bit n of `birth` is set if an inactive cube with n active neighbours
+/// becomes active, and likewise for `survival` and active cubes.
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    birth: BitVec,
+    survival: BitVec,
+}
 
-    for &(x, y, z) in frame {
-        for xd in -1..=1 {
-            for yd in -1..=1 {
-                for zd in -1..=1 {
-                    if xd == 0 && yd == 0 && zd == 0 {
-                        continue;
-                    }
+impl Rule {
+    fn new(birth: &[usize], survival: &[usize]) -> Self {
+        let bits = |counts: &[usize]| {
+            let mut bits = bitvec![0; MAX_NEIGHBOURS + 1];
+            counts.iter().for_each(|&n| bits.set(n, true));
+            bits
+        };
+        Rule {
+            birth: bits(birth),
+            survival: bits(survival),
+        }
+    }
 
-                    let inc = if zd == -1 && z == 1 { 2 } else { 1 };
+    fn conway() -> Self {
+        Rule::new(&[3], &[2, 3])
+    }
 
-                    *activity_map.entry((x + xd, y + yd, z + zd)).or_insert(0) += inc;
-                }
-            }
-        }
+    fn is_active(&self, neighbours: usize, active: bool) -> bool {
+        let counts = if active { &self.survival } else { &self.birth };
+        counts.get(neighbours).is_some_and(|bit| *bit)
     }
+}
 
-    activity_map
+fn parse_rule(raw: &str) -> Result<Rule, String> {
+    let counts = |part: &str, prefix: char| {
+        let part = part
+            .strip_prefix(prefix)
+            .ok_or_else(|| format!("rule '{}' doesn't look like B3/S23", raw))?;
+        let counts: Vec<&str> = match part {
+            "" => vec![],
+            _ if part.contains(',') => part.split(',').collect(),
+            // Otherwise every character is a count of its own.
+            _ => part.matches(|_| true).collect(),
+        };
+        counts
+            .into_iter()
+            .map(|n| match n.parse::<usize>() {
+                Ok(n) if n <= MAX_NEIGHBOURS => Ok(n),
+                Ok(n) => Err(format!("no cube has {} neighbours", n)),
+                Err(_) => Err(format!("invalid neighbour count '{}' in rule '{}'", n, raw)),
+            })
+            .collect::<Result<Vec<usize>, String>>()
+    };
+
+    let (birth, survival) = raw
+        .split_once('/')
+        .ok_or_else(|| format!("rule '{}' doesn't look like B3/S23", raw))?;
+    let birth = counts(birth, 'B')?;
+    if birth.contains(&0) {
+        return Err("B0 would fill all of space".to_string());
+    }
+    Ok(Rule::new(&birth, &counts(survival, 'S')?))
+}
+
+/// The first two coordinates are the input's x and y, the rest are extra
+/// dimensions which start at 0.
+type Point<const D: usize> = [i32; D];
+type PointSet<const D: usize> = AHashSet<Point<D>>;
+
+/// Offsets to every neighbour of a cube.
+fn neighbour_offsets<const D: usize>() -> Vec<Point<D>> {
+    let mut offsets: Vec<Point<D>> = vec![[0; D]];
+    for axis in 0..D {
+        offsets = offsets
+            .iter()
+            .flat_map(|&offset| {
+                (-1..=1).map(move |d| {
+                    let mut offset = offset;
+                    offset[axis] = d;
+                    offset
+                })
+            })
+            .collect();
+    }
+    offsets.retain(|offset| offset != &[0; D]);
+    offsets
 }
 
-fn build_p2_activity_map(frame: &PointSet4) -> AHashMap<Point4, usize> {
-    let mut activity_map: AHashMap<Point4, usize> = AHashMap::with_capacity(frame.len() * 40);
-
-    for &(x, y, z, w) in frame {
-        for xd in -1..=1 {
-            for yd in -1..=1 {
-                for zd in -1..=1 {
-                    for wd in -1..=1 {
-                        if xd == 0 && yd == 0 && zd == 0 && wd == 0 {
-                            continue;
-                        }
-
-                        let inc = if (wd == -1 && w == 1) && (zd == -1 && z == 1) {
-                            4
-                        } else if (wd == -1 && w == 1) || (zd == -1 && z == 1) {
-                            2
-                        } else {
-                            1
-                        };
-
-                        *activity_map
-                            .entry((x + xd, y + yd, z + zd, w + wd))
-                            .or_insert(0) += inc;
+/// Everything is symmetric about 0 in each extra dimension, so only cubes
+/// with none of those coordinates negative are kept. A cube at 1 in one of
+/// them neighbours the cube at 0 twice: once itself, and once as its mirror
+/// image at -1.
+fn build_activity_map<const D: usize>(
+    frame: &PointSet<D>,
+    offsets: &[Point<D>],
+) -> AHashMap<Point<D>, usize> {
+    let mut activity_map: AHashMap<Point<D>, usize> =
+        AHashMap::with_capacity(frame.len() * offsets.len() / 2);
+
+    for point in frame {
+        'offsets: for offset in offsets {
+            let mut neighbour = *point;
+            let mut inc = 1;
+            for axis in 0..D {
+                neighbour[axis] += offset[axis];
+                if axis >= 2 {
+                    if neighbour[axis] < 0 {
+                        continue 'offsets;
+                    }
+                    if offset[axis] == -1 && point[axis] == 1 {
+                        inc *= 2;
                     }
                 }
             }
+
+            *activity_map.entry(neighbour).or_insert(0) += inc;
         }
     }
 
     activity_map
 }
 
-fn turn_part1(current: &PointSet3) -> PointSet3 {
-    build_p1_activity_map(current)
-        .into_iter()
-        .filter(|&((_, _, z), _)| z >= 0)
-        .filter(|&(k, v)| v == 3 || (v == 2 && current.contains(&k)))
-        .map(|(k, _)| k)
-        .collect()
-}
+fn turn<const D: usize>(current: &PointSet<D>, offsets: &[Point<D>], rule: &Rule) -> PointSet<D> {
+    let activity_map = build_activity_map(current, offsets);
 
-fn turn_part2(current: &PointSet4) -> PointSet4 {
-    build_p2_activity_map(current)
-        .into_iter()
-        .filter(|&((_, _, z, w), _)| z >= 0 && w >= 0)
-        .filter(|&(k, v)| v == 3 || (v == 2 && current.contains(&k)))
-        .map(|(k, _)| k)
+    // Cubes with no active neighbours aren't in the map at all.
+    let lonely = current
+        .iter()
+        .filter(|&point| rule.is_active(0, true) && !activity_map.contains_key(point));
+
+    activity_map
+        .iter()
+        .filter(|&(point, &v)| rule.is_active(v, current.contains(point)))
+        .map(|(point, _)| point)
+        .chain(lonely)
+        .copied()
         .collect()
 }
 
-fn calculate_p1(data: &AHashSet<(i32, i32)>) -> usize {
-    let mut current_frame: PointSet3 = AHashSet::with_capacity(data.len());
-
-    for (x, y) in data {
-        current_frame.insert((*x, *y, 0));
-    }
+fn simulate<const D: usize>(data: &AHashSet<(i32, i32)>, cycles: usize, rule: &Rule) -> usize {
+    let offsets = neighbour_offsets::<D>();
+    let mut current_frame: PointSet<D> = data
+        .iter()
+        .map(|&(x, y)| {
+            let mut point = [0; D];
+            (point[0], point[1]) = (x, y);
+            point
+        })
+        .collect();
 
-    for _ in 0..6 {
-        current_frame = turn_part1(&current_frame);
+    for _ in 0..cycles {
+        current_frame = turn(&current_frame, &offsets, rule);
     }
 
+    // Each cube also stands for its mirror images in every extra dimension
+    // it's off 0 in.
     current_frame
-        .into_iter()
-        .map(|(_, _, z)| if z == 0 { 1 } else { 2 })
+        .iter()
+        .map(|point| 1 << point[2..].iter().filter(|&&c| c != 0).count())
         .sum()
 }
 
-fn calculate_p2(data: &AHashSet<(i32, i32)>) -> usize {
-    let mut current_frame: PointSet4 = AHashSet::with_capacity(data.len());
-
-    for (x, y) in data {
-        current_frame.insert((*x, *y, 0, 0));
+fn count_active(dimensions: u8, data: &AHashSet<(i32, i32)>, cycles: usize, rule: &Rule) -> usize {
+    match dimensions {
+        2 => simulate::<2>(data, cycles, rule),
+        3 => simulate::<3>(data, cycles, rule),
+        4 => simulate::<4>(data, cycles, rule),
+        5 => simulate::<5>(data, cycles, rule),
+        6 => simulate::<6>(data, cycles, rule),
+        7 => simulate::<7>(data, cycles, rule),
+        8 => simulate::<8>(data, cycles, rule),
+        _ => panic!("can't simulate {} dimensions", dimensions),
     }
+}
 
-    for _ in 0..6 {
-        current_frame = turn_part2(&current_frame);
-    }
+fn calculate_p1(data: &AHashSet<(i32, i32)>) -> usize {
+    simulate::<3>(data, 6, &Rule::conway())
+}
 
-    current_frame
-        .into_iter()
-        .map(|(_, _, z, w)| {
-            if z == 0 && w == 0 {
-                1
-            } else if z == 0 || w == 0 {
-                2
-            } else {
-                4
-            }
-        })
-        .sum()
+fn calculate_p2(data: &AHashSet<(i32, i32)>) -> usize {
+    simulate::<4>(data, 6, &Rule::conway())
 }
 
 fn main() {
     let args = Cli::parse();
     let raw_inp = fs::read_to_string(args.input).expect("can't open input file");
     let data = parse(&raw_inp);
-    let p1 = calculate_p1(&data);
-    let p2 = calculate_p2(&data);
-    println!("{}\n{}", p1, p2);
+    if args.dimensions.is_empty() {
+        let p1 = calculate_p1(&data);
+        let p2 = calculate_p2(&data);
+        println!("{}\n{}", p1, p2);
+        return;
+    }
+    let answers: Vec<String> = args
+        .dimensions
+        .iter()
+        .map(|&d| count_active(d, &data, args.cycles, &args.rule).to_string())
+        .collect();
+    println!("{}", answers.join("\n"));
 }
 
 #[cfg(test)]
@@ -173,6 +261,40 @@ mod tests {
         assert_eq!(calculate_p2(&parse(EXAMPLE_DATA)), 848);
     }
 
+    #[test]
+    fn test_dimensions() {
+        let data = parse(EXAMPLE_DATA);
+        assert_eq!(count_active(2, &data, 4, &Rule::conway()), 5);
+        assert_eq!(count_active(5, &data, 6, &Rule::conway()), 5760);
+        assert_eq!(neighbour_offsets::<5>().len(), 242);
+    }
+
+    #[test]
+    fn test_rules() {
+        assert_eq!(parse_rule("B3/S23"), Ok(Rule::conway()));
+        assert_eq!(parse_rule("B36/S"), Ok(Rule::new(&[3, 6], &[])));
+        assert!(parse_rule("3/23").is_err());
+        assert!(parse_rule("B3/Sx").is_err());
+        assert!(parse_rule("B03/S23").is_err());
+
+        // Counts above 9 need commas between them.
+        let wide = parse_rule("B3,12/S2,3,6560").unwrap();
+        assert_eq!(wide, Rule::new(&[3, 12], &[2, 3, 6560]));
+        assert!(wide.is_active(12, false) && wide.is_active(6560, true));
+        assert!(!wide.is_active(6561, true));
+        assert_eq!(
+            parse_rule("B3/S2,6561"),
+            Err("no cube has 6561 neighbours".to_string())
+        );
+        assert!(parse_rule("B3,/S2").is_err());
+
+        let data = parse(EXAMPLE_DATA);
+        let highlife = parse_rule("B36/S23").unwrap();
+        assert_eq!(count_active(3, &data, 4, &highlife), 95);
+        let seeds = parse_rule("B2/S").unwrap();
+        assert_eq!(count_active(4, &data, 3, &seeds), 712);
+    }
+
     #[test]
     fn test_p1_real() {
         assert_eq!(calculate_p1(&parse(REAL_DATA)), 242);
@@ -0,0 +1,28 @@
+/// SplitMix64: small, fast and seedable, which is all the puzzles need for
+/// reproducible random inputs and samples.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..n`, rejecting draws from the incomplete final bucket.
+    pub fn below(&mut self, n: u128) -> u128 {
+        let zone = u128::MAX - u128::MAX % n;
+        loop {
+            let x = (self.next_u64() as u128) << 64 | self.next_u64() as u128;
+            if x < zone {
+                return x % n;
+            }
+        }
+    }
+}